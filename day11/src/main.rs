@@ -1,4 +1,4 @@
-use intcode::{IntcodeMachine, ProgramError, ProgramState};
+use intcode::{IntcodeMachine, OutputEvent, ProgramError};
 use std::collections::HashMap;
 use std::fmt;
 
@@ -26,20 +26,19 @@ fn paint_picture(program: Vec<i64>, initial_colour: Colour) -> Result<Picture, P
         picture.pixels.insert((0, 0), Colour::White);
     }
 
-    loop {
-        match machine.run()? {
-            ProgramState::Completed(outputs) => {
-                picture.paint(&outputs);
-                break;
-            },
-            ProgramState::PendingInput(outputs) => {
-                picture.paint(&outputs);
-
-                machine.add_input(match picture.current_colour() {
+    let mut commands = machine.output_groups(2);
+    while let Some(event) = commands.next() {
+        match event? {
+            OutputEvent::Output(command) => picture.paint(&command),
+            OutputEvent::NeedsInput => {
+                commands.add_input(match picture.current_colour() {
                     Colour::Black => 0,
                     Colour::White => 1,
                 });
             }
+            OutputEvent::Halted | OutputEvent::Breakpoint(_) => {
+                unreachable!("Halted ends the iterator and no breakpoints have been set")
+            }
         }
     }
 
@@ -110,6 +110,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     }
                 }
             }
+            ProgramState::Breakpoint(_, _) => unreachable!("No breakpoints have been set"),
         }
     }
 
@@ -1,56 +1,179 @@
-pub mod permutation {
-    pub struct Permutations<T> {
-        items: Vec<T>,
-        stack: Vec<usize>,
-        counter: usize,
-        is_first: bool,
+pub mod permutation;
+
+pub mod search {
+    use crate::permutation::{Combinations, Permutations};
+    use std::thread;
+
+    // No more phase settings than this can be searched. 12! orderings is
+    // already almost half a billion, and still fits in a 32-bit `usize`.
+    pub const MAX_PHASES: usize = 12;
+
+    // Every ordering of `count` distinct phases chosen from `phases`, in the
+    // order the sequential search visits them. They are made as needed.
+    pub fn orderings(phases: &[i64], count: usize) -> impl Iterator<Item = Vec<i64>> {
+        Combinations::of(phases.to_vec(), count).flat_map(Permutations::of)
     }
 
-    impl<T> Permutations<T> {
-        pub fn of(items: Vec<T>) -> Permutations<T>{
-            let n = items.len();
-            Permutations {
-                items,
-                stack: vec![0; n],
-                counter: 0,
-                is_first: true,
-            }
+    fn ordering_count(phases: usize, count: usize) -> usize {
+        if count > phases {
+            return 0;
+        }
+        (phases - count + 1..=phases).product()
+    }
+
+    // As `orderings`, but starting from the ordering at `start`. Whole
+    // combinations, each with `count!` orderings, are skipped without
+    // permuting them.
+    fn orderings_from(
+        phases: &[i64],
+        count: usize,
+        start: usize,
+    ) -> impl Iterator<Item = Vec<i64>> {
+        let per_combination = ordering_count(count, count);
+        Combinations::of(phases.to_vec(), count)
+            .skip(start / per_combination)
+            .flat_map(Permutations::of)
+            .skip(start % per_combination)
+    }
+
+    // An ordering and the signal it gives.
+    type Best = Option<(Vec<i64>, i64)>;
+
+    // As with `Iterator::max_by_key`, a later ordering replaces an equally
+    // good one.
+    fn keep_best(best: &mut Best, ordering: Vec<i64>, signal: i64) {
+        if best
+            .as_ref()
+            .map_or(true, |&(_, best_signal)| signal >= best_signal)
+        {
+            *best = Some((ordering, signal));
         }
     }
 
-    impl<T> Iterator for Permutations<T>
-    where T: Clone
+    // Tries every ordering and returns the one giving the strongest signal.
+    // As with `Iterator::max_by_key`, the last of several equally good
+    // orderings wins.
+    pub fn best_sequential<F, E>(
+        phases: &[i64],
+        count: usize,
+        evaluate: F,
+    ) -> Result<Option<(Vec<i64>, i64)>, E>
+    where
+        F: Fn(&[i64]) -> Result<i64, E>,
     {
-        type Item = Vec<T>;
-
-        fn next(&mut self) -> Option<Self::Item> {
-            // Heap's Algorithm for permutations of a list.
-            // https://en.wikipedia.org/wiki/Heap%27s_algorithm
-            
-            if self.is_first {
-                self.is_first = false;
-                return Some(self.items.clone());
-            }
+        assert!(phases.len() <= MAX_PHASES, "Too many phase settings");
+
+        let mut best = None;
+        for ordering in orderings(phases, count) {
+            let signal = evaluate(&ordering)?;
+            keep_best(&mut best, ordering, signal);
+        }
+        Ok(best)
+    }
 
-            while self.counter <= self.stack[self.counter] {
-                self.stack[self.counter] = 0;
-                self.counter += 1;
+    // As `best_sequential`, but with the orderings shared out between
+    // `threads` worker threads, each making its own share as it goes. The
+    // result, including which error is reported if several orderings fail,
+    // is the same.
+    pub fn best_parallel<F, E>(
+        phases: &[i64],
+        count: usize,
+        threads: usize,
+        evaluate: F,
+    ) -> Result<Option<(Vec<i64>, i64)>, E>
+    where
+        F: Fn(&[i64]) -> Result<i64, E> + Sync,
+        E: Send,
+    {
+        assert!(phases.len() <= MAX_PHASES, "Too many phase settings");
 
-                if self.counter >= self.items.len() {
-                    return None;
-                }
+        let total = ordering_count(phases.len(), count);
+        if total == 0 {
+            return Ok(None);
+        }
+
+        let chunk_size = total.div_ceil(threads.max(1));
+        let evaluate = &evaluate;
+
+        // Each worker returns its best ordering and signal, or stops at its
+        // first error.
+        let results: Vec<Result<Best, E>> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..total)
+                .step_by(chunk_size)
+                .map(|start| {
+                    scope.spawn(move || {
+                        let mut best = None;
+                        for ordering in orderings_from(phases, count, start).take(chunk_size) {
+                            let signal = evaluate(&ordering)?;
+                            keep_best(&mut best, ordering, signal);
+                        }
+                        Ok(best)
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("Worker thread panicked"))
+                .collect()
+        });
+
+        let mut best = None;
+        for result in results {
+            if let Some((ordering, signal)) = result? {
+                keep_best(&mut best, ordering, signal);
             }
+        }
+
+        Ok(best)
+    }
 
-            if self.counter % 2 == 0 {
-                self.items.swap(0, self.counter);
-            } else {
-                self.items.swap(self.stack[self.counter], self.counter)
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::collections::HashSet;
+
+        #[test]
+        fn orderings_of_a_subset() {
+            let all: Vec<_> = orderings(&[1, 2, 3, 4], 2).collect();
+            assert_eq!(all.len(), 12);
+            assert_eq!(all.iter().collect::<HashSet<_>>().len(), 12);
+            assert_eq!(orderings(&[1, 2], 3).next(), None);
+        }
+
+        #[test]
+        fn orderings_can_start_part_way() {
+            let phases = [1, 2, 3, 4, 5];
+            let all: Vec<_> = orderings(&phases, 3).collect();
+            for start in 0..all.len() {
+                let rest: Vec<_> = orderings_from(&phases, 3, start).collect();
+                assert_eq!(rest, &all[start..]);
             }
+        }
 
-            self.stack[self.counter] += 1;
-            self.counter = 0;
+        #[test]
+        fn parallel_search_matches_sequential() {
+            // Ties are broken in favour of the later ordering.
+            let score = |phases: &[i64]| -> Result<i64, ()> { Ok(phases[0] * 10 - phases[1] % 2) };
+            let phases: Vec<i64> = (0..=6).collect();
+            let expected = best_sequential(&phases, 3, score);
+            for threads in 1..=8 {
+                assert_eq!(best_parallel(&phases, 3, threads, score), expected);
+            }
+        }
 
-            Some(self.items.clone())
+        #[test]
+        fn first_error_is_reported() {
+            let fail_on_odd = |phases: &[i64]| {
+                if phases[0] % 2 == 1 {
+                    Err(phases.to_vec())
+                } else {
+                    Ok(0)
+                }
+            };
+            let expected = best_sequential(&[0, 1, 2, 3], 4, fail_on_odd);
+            assert!(expected.is_err());
+            assert_eq!(best_parallel(&[0, 1, 2, 3], 4, 3, fail_on_odd), expected);
         }
     }
 }
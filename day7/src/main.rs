@@ -1,5 +1,95 @@
-use day7::permutation::Permutations;
+use day7::search::{best_parallel, MAX_PHASES};
 use intcode::*;
+use std::collections::HashSet;
+use std::process;
+use std::thread;
+
+const USAGE: &str =
+    "Usage: day7 [--phases LIST] [--feedback-phases LIST] [--amplifiers N] [--threads N]
+
+LIST is a comma-separated list of phase settings or inclusive ranges,
+for example 0..=4 or 0,1,5..=7. By default there is one amplifier per
+phase setting.";
+
+#[derive(Debug, Eq, PartialEq)]
+struct Options {
+    phases: Vec<i64>,
+    feedback_phases: Vec<i64>,
+    amplifiers: Option<usize>,
+    threads: usize,
+}
+
+fn parse_phases(text: &str) -> Result<Vec<i64>, String> {
+    let too_many = || format!("There can be at most {} phase settings", MAX_PHASES);
+    let mut phases = Vec::new();
+    for item in text.split(',') {
+        let item = item.trim();
+        let parse = |n: &str| {
+            n.trim()
+                .parse::<i64>()
+                .map_err(|_| format!("Invalid phase setting {}", n))
+        };
+        match item.find("..=") {
+            Some(split) => {
+                let (start, end) = (parse(&item[..split])?, parse(&item[split + 3..])?);
+                if start > end {
+                    return Err(format!("Empty range of phase settings {}", item));
+                }
+                if end.saturating_sub(start) >= MAX_PHASES as i64 {
+                    return Err(too_many());
+                }
+                phases.extend(start..=end);
+            }
+            None => phases.push(parse(item)?),
+        }
+    }
+    if phases.len() > MAX_PHASES {
+        return Err(too_many());
+    }
+
+    // Each phase setting can only be used once.
+    let mut seen = HashSet::new();
+    for phase in &phases {
+        if !seen.insert(phase) {
+            return Err(format!("Phase setting {} is repeated", phase));
+        }
+    }
+    Ok(phases)
+}
+
+fn parse_args<I>(mut args: I) -> Result<Options, String>
+where
+    I: Iterator<Item = String>,
+{
+    let mut options = Options {
+        phases: (0..=4).collect(),
+        feedback_phases: (5..=9).collect(),
+        amplifiers: None,
+        threads: thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {}", flag))?;
+        let count = || {
+            value
+                .parse::<usize>()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| format!("Invalid value for {}: {}", flag, value))
+        };
+        match flag.as_str() {
+            "--phases" => options.phases = parse_phases(&value)?,
+            "--feedback-phases" => options.feedback_phases = parse_phases(&value)?,
+            "--amplifiers" => options.amplifiers = Some(count()?),
+            "--threads" => options.threads = count()?,
+            _ => return Err(format!("Unknown option {}", flag)),
+        }
+    }
+
+    Ok(options)
+}
 
 fn main() {
     let program = vec![
@@ -25,63 +115,81 @@ fn main() {
         9, 3, 9, 101, 2, 9, 9, 4, 9, 99,
     ];
 
-    println!("Part 1:");
-
-    let (phases, signal) = Permutations::of(vec![0, 1, 2, 3, 4])
-        .map(|phases| {
-            let signal = thruster_signal(&program, &phases).unwrap();
-            (phases, signal)
-        })
-        .max_by_key(|(_, signal)| *signal)
-        .unwrap();
+    let options = parse_args(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        process::exit(1);
+    });
 
-    println!("Phases: {:?} give signal strength {}", phases, signal);
+    println!("Part 1:");
+    let count = options.amplifiers.unwrap_or(options.phases.len());
+    report(best_parallel(
+        &options.phases,
+        count,
+        options.threads,
+        |phases| thruster_signal(&program, phases),
+    ));
 
     println!("Part 2:");
-
-    let (phases, signal) = Permutations::of(vec![5, 6, 7, 8, 9])
-        .map(|phases| {
-            let signal = feedback_loop(&program, &phases).unwrap();
-            (phases, signal)
-        })
-        .max_by_key(|(_, signal)| *signal)
-        .unwrap();
-
-    println!("Phases: {:?} give signal strength {}", phases, signal);
+    let count = options.amplifiers.unwrap_or(options.feedback_phases.len());
+    report(best_parallel(
+        &options.feedback_phases,
+        count,
+        options.threads,
+        |phases| feedback_loop(&program, phases),
+    ));
 }
 
-fn thruster_signal(program: &[i64], phases: &[i64]) -> Result<i64, ProgramError> {
-    let mut input = 0;
-    for phase in phases {
-        let output = run(&mut program.to_owned(), vec![*phase, input])?;
-        input = output[0];
+fn report(best: Result<Option<(Vec<i64>, i64)>, DataflowError>) {
+    match best {
+        Ok(Some((phases, signal))) => {
+            println!("Phases: {:?} give signal strength {}", phases, signal)
+        }
+        Ok(None) => println!("There are more amplifiers than phase settings"),
+        Err(e) => println!("Error: {}", e),
     }
+}
 
-    Ok(input)
+fn thruster_signal(program: &[i64], phases: &[i64]) -> Result<i64, DataflowError> {
+    amplifiers(program, phases, false)
 }
 
-fn feedback_loop(program: &[i64], phases: &[i64]) -> Result<i64, ProgramError> {
-    let mut amplifiers: Vec<IntcodeMachine> = phases
+fn feedback_loop(program: &[i64], phases: &[i64]) -> Result<i64, DataflowError> {
+    amplifiers(program, phases, true)
+}
+
+// A chain of amplifiers, one per phase setting, with the first given a
+// signal of 0. With feedback, the last amplifier's output also goes back to
+// the first. The signal is the last value the last amplifier outputs before
+// halting, which without feedback is normally its only one.
+fn amplifiers(program: &[i64], phases: &[i64], feedback: bool) -> Result<i64, DataflowError> {
+    if phases.is_empty() {
+        return Err(DataflowError::NoNodes);
+    }
+
+    let mut graph = Dataflow::new();
+    let nodes: Vec<NodeId> = phases
         .iter()
-        .map(|&phase| IntcodeMachine::with_seed(program.to_owned(), phase))
+        .enumerate()
+        .map(|(index, &phase)| graph.add_node(&format!("amp{}", index), program, vec![phase]))
         .collect();
-    let last_amplifier = amplifiers.len() - 1;
-    let mut previous_output = vec![0];
 
-    loop {
-        for (index, amplifier) in amplifiers.iter_mut().enumerate() {
-            amplifier.add_inputs(previous_output);
-            let output = amplifier.run()?;
+    for pair in nodes.windows(2) {
+        let name = format!("{}-{}", graph.name(pair[0]), graph.name(pair[1]));
+        graph.connect(&name, pair[0], pair[1]);
+    }
 
-            match output {
-                ProgramState::Completed(mut values) if index == last_amplifier => {
-                    return Ok(values.pop().expect("No output from last amplifier!"));
-                }
-                ProgramState::PendingInput(values) | ProgramState::Completed(values) => {
-                    previous_output = values;
-                }
-            }
-        }
+    let first = nodes[0];
+    let last = nodes[nodes.len() - 1];
+    if feedback {
+        let name = format!("{}-{}", graph.name(last), graph.name(first));
+        graph.connect(&name, last, first);
+    }
+    graph.send(first, 0);
+
+    let outputs = graph.run_until_halted(last)?;
+    match outputs.last() {
+        Some(&signal) => Ok(signal),
+        None => Err(DataflowError::NoOutput(graph.name(last).to_string())),
     }
 }
 
@@ -140,4 +248,73 @@ mod tests {
         let result = feedback_loop(&program, &[9, 7, 8, 5, 6]);
         assert_eq!(result, Ok(18216))
     }
+
+    #[test]
+    fn no_amplifiers() {
+        assert_eq!(thruster_signal(&[99], &[]), Err(DataflowError::NoNodes));
+    }
+
+    #[test]
+    fn signal_is_the_last_output() {
+        // Reads its phase and signal, and outputs both.
+        let program = vec![3, 9, 3, 10, 4, 9, 4, 10, 99, 0, 0];
+        assert_eq!(thruster_signal(&program, &[7]), Ok(0));
+
+        // Reads both inputs but outputs nothing.
+        let program = vec![3, 5, 3, 5, 99, 0];
+        assert_eq!(
+            thruster_signal(&program, &[1]),
+            Err(DataflowError::NoOutput("amp0".to_string()))
+        );
+    }
+
+    #[test]
+    fn parallel_search_finds_examples() {
+        let program = vec![
+            3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0,
+        ];
+        let best = best_parallel(&[0, 1, 2, 3, 4], 5, 4, |phases| {
+            thruster_signal(&program, phases)
+        });
+        assert_eq!(best, Ok(Some((vec![4, 3, 2, 1, 0], 43210))));
+
+        let program = vec![
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1,
+            28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        let best = best_parallel(&[5, 6, 7, 8, 9], 5, 4, |phases| {
+            feedback_loop(&program, phases)
+        });
+        assert_eq!(best, Ok(Some((vec![9, 8, 7, 6, 5], 139_629_729))));
+    }
+
+    #[test]
+    fn phase_lists() {
+        assert_eq!(parse_phases("0..=4"), Ok(vec![0, 1, 2, 3, 4]));
+        assert_eq!(parse_phases("1, 5..=7,-2"), Ok(vec![1, 5, 6, 7, -2]));
+        assert!(parse_phases("1,x").is_err());
+        assert!(parse_phases("5..=1").is_err());
+        assert!(parse_phases("0,0,1").is_err());
+        assert!(parse_phases("0..=2,2").is_err());
+        assert!(parse_phases("0..=9999999999").is_err());
+        assert!(parse_phases("-9223372036854775808..=9223372036854775807").is_err());
+        assert!(parse_phases("0..=9,10,11,12").is_err());
+        assert!(parse_phases("0..=11").is_ok());
+    }
+
+    #[test]
+    fn command_line_options() {
+        let args = "--phases 0..=2 --amplifiers 2 --threads 3"
+            .split(' ')
+            .map(String::from);
+        let options = parse_args(args).unwrap();
+        assert_eq!(options.phases, vec![0, 1, 2]);
+        assert_eq!(options.feedback_phases, vec![5, 6, 7, 8, 9]);
+        assert_eq!(options.amplifiers, Some(2));
+        assert_eq!(options.threads, 3);
+
+        assert!(parse_args(vec!["--threads".to_string()].into_iter()).is_err());
+        assert!(parse_args("--threads 0".split(' ').map(String::from)).is_err());
+        assert!(parse_args("--speed 3".split(' ').map(String::from)).is_err());
+    }
 }
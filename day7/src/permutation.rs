@@ -0,0 +1,548 @@
+// Each iterator here can also be stepped with `next_borrowed`, which
+// rearranges the iterator's own copy of the items and lends it out instead
+// of allocating a new `Vec` for every item.
+//
+// They are all `ExactSizeIterator`s, so the number of items each would
+// yield must fit in a `usize`. `try_of` returns `None` when it doesn't (from
+// 21 items upwards for full permutations on 64-bit targets), and `of`
+// panics.
+
+use std::convert::TryFrom;
+
+fn factorial(n: usize) -> Option<usize> {
+    (1..=n).try_fold(1usize, |product, k| product.checked_mul(k))
+}
+
+fn falling_factorial(n: usize, k: usize) -> Option<usize> {
+    if k > n {
+        return Some(0);
+    }
+    (n - k + 1..=n).try_fold(1usize, |product, k| product.checked_mul(k))
+}
+
+fn binomial(n: usize, k: usize) -> Option<usize> {
+    if k > n {
+        return Some(0);
+    }
+    let k = k.min(n - k);
+    let mut result: u128 = 1;
+    for i in 0..k {
+        // Exact at every step, since this is C(n, i + 1).
+        result = result.checked_mul((n - i) as u128)? / (i as u128 + 1);
+    }
+    usize::try_from(result).ok()
+}
+
+// Every ordering of the items, in the order given by Heap's algorithm, which
+// gets from one to the next with a single swap.
+pub struct Permutations<T> {
+    items: Vec<T>,
+    stack: Vec<usize>,
+    counter: usize,
+    is_first: bool,
+    remaining: usize,
+}
+
+impl<T> Permutations<T> {
+    pub fn of(items: Vec<T>) -> Permutations<T> {
+        Permutations::try_of(items).expect("Too many permutations to count")
+    }
+
+    pub fn try_of(items: Vec<T>) -> Option<Permutations<T>> {
+        let n = items.len();
+        Some(Permutations {
+            remaining: factorial(n)?,
+            items,
+            stack: vec![0; n],
+            counter: 0,
+            is_first: true,
+        })
+    }
+
+    pub fn next_borrowed(&mut self) -> Option<&[T]> {
+        // Heap's Algorithm for permutations of a list.
+        // https://en.wikipedia.org/wiki/Heap%27s_algorithm
+
+        if self.is_first {
+            self.is_first = false;
+        } else if !self.advance() {
+            return None;
+        }
+
+        self.remaining -= 1;
+        Some(&self.items)
+    }
+
+    fn advance(&mut self) -> bool {
+        while self.counter < self.items.len() && self.counter <= self.stack[self.counter] {
+            self.stack[self.counter] = 0;
+            self.counter += 1;
+        }
+
+        if self.counter >= self.items.len() {
+            return false;
+        }
+
+        if self.counter % 2 == 0 {
+            self.items.swap(0, self.counter);
+        } else {
+            self.items.swap(self.stack[self.counter], self.counter)
+        }
+
+        self.stack[self.counter] += 1;
+        self.counter = 0;
+        true
+    }
+}
+
+impl<T> Iterator for Permutations<T>
+where
+    T: Clone,
+{
+    type Item = Vec<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_borrowed().map(<[T]>::to_vec)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<T: Clone> ExactSizeIterator for Permutations<T> {}
+
+// Every ordering of `k` of the items, in lexicographic order of their
+// positions in the original list. Sorted items therefore come out in
+// lexicographic order.
+pub struct KPermutations<T> {
+    // The current k-permutation is the first `k` items.
+    items: Vec<T>,
+    cycles: Vec<usize>,
+    k: usize,
+    is_first: bool,
+    done: bool,
+    remaining: usize,
+}
+
+impl<T> KPermutations<T> {
+    pub fn of(items: Vec<T>, k: usize) -> KPermutations<T> {
+        KPermutations::try_of(items, k).expect("Too many permutations to count")
+    }
+
+    pub fn try_of(items: Vec<T>, k: usize) -> Option<KPermutations<T>> {
+        let n = items.len();
+        Some(KPermutations {
+            cycles: (0..k.min(n)).map(|i| n - i).collect(),
+            remaining: falling_factorial(n, k)?,
+            done: k > n,
+            items,
+            k,
+            is_first: true,
+        })
+    }
+
+    pub fn next_borrowed(&mut self) -> Option<&[T]> {
+        if self.done {
+            return None;
+        }
+
+        if self.is_first {
+            self.is_first = false;
+        } else if !self.advance() {
+            self.done = true;
+            return None;
+        }
+
+        self.remaining -= 1;
+        Some(&self.items[..self.k])
+    }
+
+    // The same method as Python's `itertools.permutations`, applied to the
+    // items themselves instead of to their indices.
+    fn advance(&mut self) -> bool {
+        let n = self.items.len();
+        for i in (0..self.k).rev() {
+            self.cycles[i] -= 1;
+            if self.cycles[i] == 0 {
+                self.items[i..].rotate_left(1);
+                self.cycles[i] = n - i;
+            } else {
+                let j = n - self.cycles[i];
+                self.items.swap(i, j);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl<T> Iterator for KPermutations<T>
+where
+    T: Clone,
+{
+    type Item = Vec<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_borrowed().map(<[T]>::to_vec)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<T: Clone> ExactSizeIterator for KPermutations<T> {}
+
+// Every ordering of all of the items, in lexicographic order of position.
+pub fn lexicographic<T>(items: Vec<T>) -> KPermutations<T> {
+    let n = items.len();
+    KPermutations::of(items, n)
+}
+
+// Every choice of `k` of the items, keeping them in their original order,
+// in lexicographic order of position.
+pub struct Combinations<T> {
+    items: Vec<T>,
+    indices: Vec<usize>,
+    current: Vec<T>,
+    is_first: bool,
+    done: bool,
+    remaining: usize,
+}
+
+impl<T: Clone> Combinations<T> {
+    pub fn of(items: Vec<T>, k: usize) -> Combinations<T> {
+        Combinations::try_of(items, k).expect("Too many combinations to count")
+    }
+
+    pub fn try_of(items: Vec<T>, k: usize) -> Option<Combinations<T>> {
+        let n = items.len();
+        let indices: Vec<usize> = (0..k.min(n)).collect();
+        Some(Combinations {
+            remaining: binomial(n, k)?,
+            current: indices.iter().map(|&i| items[i].clone()).collect(),
+            done: k > n,
+            items,
+            indices,
+            is_first: true,
+        })
+    }
+
+    pub fn next_borrowed(&mut self) -> Option<&[T]> {
+        if self.done {
+            return None;
+        }
+
+        if self.is_first {
+            self.is_first = false;
+        } else if !self.advance() {
+            self.done = true;
+            return None;
+        }
+
+        self.remaining -= 1;
+        Some(&self.current)
+    }
+
+    // Only the items at changed positions are cloned.
+    fn advance(&mut self) -> bool {
+        let n = self.items.len();
+        let k = self.indices.len();
+
+        let i = match (0..k).rev().find(|&i| self.indices[i] != i + n - k) {
+            Some(i) => i,
+            None => return false,
+        };
+
+        self.indices[i] += 1;
+        for j in i + 1..k {
+            self.indices[j] = self.indices[j - 1] + 1;
+        }
+        for j in i..k {
+            self.current[j] = self.items[self.indices[j]].clone();
+        }
+        true
+    }
+}
+
+impl<T> Iterator for Combinations<T>
+where
+    T: Clone,
+{
+    type Item = Vec<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_borrowed().map(<[T]>::to_vec)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<T: Clone> ExactSizeIterator for Combinations<T> {}
+
+// Every distinct ordering of items which may include duplicates, in
+// lexicographic order. `[1, 1, 2]` gives three orderings, not six.
+pub struct MultisetPermutations<T> {
+    items: Vec<T>,
+    is_first: bool,
+    done: bool,
+    remaining: usize,
+}
+
+impl<T: Ord> MultisetPermutations<T> {
+    pub fn of(items: Vec<T>) -> MultisetPermutations<T> {
+        MultisetPermutations::try_of(items).expect("Too many permutations to count")
+    }
+
+    pub fn try_of(mut items: Vec<T>) -> Option<MultisetPermutations<T>> {
+        items.sort();
+
+        // n! / (c1! c2! ...), where the c are the sizes of each run of equal
+        // items, built up one item at a time to put off overflow.
+        let mut remaining = Some(1usize);
+        let mut run = 0;
+        for (i, item) in items.iter().enumerate() {
+            run = if i > 0 && items[i - 1] == *item {
+                run + 1
+            } else {
+                1
+            };
+            remaining = remaining
+                .and_then(|count| count.checked_mul(i + 1))
+                .map(|count| count / run);
+        }
+
+        Some(MultisetPermutations {
+            remaining: remaining?,
+            items,
+            is_first: true,
+            done: false,
+        })
+    }
+
+    pub fn next_borrowed(&mut self) -> Option<&[T]> {
+        if self.done {
+            return None;
+        }
+
+        if self.is_first {
+            self.is_first = false;
+        } else if !self.advance() {
+            self.done = true;
+            return None;
+        }
+
+        self.remaining -= 1;
+        Some(&self.items)
+    }
+
+    // The classic next-permutation step, which skips over orderings that
+    // only swap equal items.
+    fn advance(&mut self) -> bool {
+        let items = &mut self.items;
+        let pivot = match (1..items.len()).rev().find(|&i| items[i - 1] < items[i]) {
+            Some(i) => i - 1,
+            None => return false,
+        };
+
+        let successor = (pivot + 1..items.len())
+            .rev()
+            .find(|&i| items[i] > items[pivot])
+            .expect("Some later item is larger than the pivot");
+        items.swap(pivot, successor);
+        items[pivot + 1..].reverse();
+        true
+    }
+}
+
+impl<T> Iterator for MultisetPermutations<T>
+where
+    T: Clone + Ord,
+{
+    type Item = Vec<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_borrowed().map(<[T]>::to_vec)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<T: Clone + Ord> ExactSizeIterator for MultisetPermutations<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck::quickcheck;
+    use std::collections::HashSet;
+    use std::fmt::Debug;
+    use std::hash::Hash;
+
+    // Checks that an iterator's items are distinct, that there are as many as
+    // it promised, and that its length counts down one item at a time.
+    fn check_counts<I>(mut iter: I, expected: usize) -> Vec<I::Item>
+    where
+        I: ExactSizeIterator,
+        I::Item: Clone + Debug + Eq + Hash,
+    {
+        assert_eq!(iter.len(), expected);
+        let mut items = Vec::new();
+        while let Some(item) = iter.next() {
+            items.push(item);
+            assert_eq!(iter.len(), expected - items.len());
+        }
+        assert_eq!(iter.next(), None);
+        assert_eq!(items.len(), expected);
+        assert_eq!(
+            items.iter().cloned().collect::<HashSet<_>>().len(),
+            expected
+        );
+        items
+    }
+
+    #[test]
+    fn heap_order_is_unchanged() {
+        let all: Vec<Vec<i32>> = Permutations::of(vec![1, 2, 3]).collect();
+        assert_eq!(
+            all,
+            vec![
+                vec![1, 2, 3],
+                vec![2, 1, 3],
+                vec![3, 1, 2],
+                vec![1, 3, 2],
+                vec![2, 3, 1],
+                vec![3, 2, 1]
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_inputs() {
+        assert_eq!(
+            check_counts(Permutations::of(Vec::<i32>::new()), 1),
+            vec![vec![]]
+        );
+        assert_eq!(
+            check_counts(lexicographic(Vec::<i32>::new()), 1),
+            vec![vec![]]
+        );
+        assert_eq!(
+            check_counts(Combinations::of(vec![1, 2], 0), 1),
+            vec![vec![]]
+        );
+        assert_eq!(check_counts(KPermutations::of(vec![1, 2], 3), 0).len(), 0);
+        assert_eq!(check_counts(Combinations::of(vec![1, 2], 3), 0).len(), 0);
+    }
+
+    #[test]
+    fn lexicographic_order() {
+        let all: Vec<Vec<i32>> = lexicographic(vec![1, 2, 3]).collect();
+        let mut sorted = all.clone();
+        sorted.sort();
+        assert_eq!(all, sorted);
+        assert_eq!(all.len(), 6);
+    }
+
+    #[test]
+    fn k_permutations_in_order() {
+        let all: Vec<Vec<char>> = KPermutations::of(vec!['a', 'b', 'c'], 2).collect();
+        assert_eq!(
+            all,
+            vec![
+                vec!['a', 'b'],
+                vec!['a', 'c'],
+                vec!['b', 'a'],
+                vec!['b', 'c'],
+                vec!['c', 'a'],
+                vec!['c', 'b']
+            ]
+        );
+    }
+
+    #[test]
+    fn combinations_in_order() {
+        let all: Vec<Vec<i32>> = Combinations::of(vec![1, 2, 3, 4], 2).collect();
+        assert_eq!(
+            all,
+            vec![
+                vec![1, 2],
+                vec![1, 3],
+                vec![1, 4],
+                vec![2, 3],
+                vec![2, 4],
+                vec![3, 4]
+            ]
+        );
+    }
+
+    #[test]
+    fn repeated_items() {
+        let all: Vec<Vec<i32>> = MultisetPermutations::of(vec![2, 1, 1]).collect();
+        assert_eq!(all, vec![vec![1, 1, 2], vec![1, 2, 1], vec![2, 1, 1]]);
+    }
+
+    #[test]
+    fn borrowing_matches_owned() {
+        let mut borrowed = Vec::new();
+        let mut permutations = Permutations::of(vec![1, 2, 3, 4]);
+        while let Some(items) = permutations.next_borrowed() {
+            borrowed.push(items.to_vec());
+        }
+        assert_eq!(
+            borrowed,
+            Permutations::of(vec![1, 2, 3, 4]).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn huge_counts_are_rejected() {
+        let items: Vec<u64> = (0..100).collect();
+        assert!(Permutations::try_of(items[..30].to_vec()).is_none());
+        assert!(KPermutations::try_of(items.clone(), 20).is_none());
+        assert!(MultisetPermutations::try_of(items.clone()).is_none());
+        assert!(Combinations::try_of(items.clone(), 50).is_none());
+        assert_eq!(
+            Combinations::of(items[..60].to_vec(), 30).len(),
+            118_264_581_564_861_424
+        );
+    }
+
+    quickcheck! {
+        fn permutation_counts(n: u8) -> bool {
+            let n = usize::from(n % 7);
+            let items: Vec<usize> = (0..n).collect();
+            let expected = factorial(n).unwrap();
+            let heap = check_counts(Permutations::of(items.clone()), expected);
+            let lex = check_counts(lexicographic(items), expected);
+            heap.into_iter().collect::<HashSet<_>>() == lex.into_iter().collect::<HashSet<_>>()
+        }
+
+        fn k_permutation_counts(n: u8, k: u8) -> bool {
+            let (n, k) = (usize::from(n % 7), usize::from(k % 8));
+            let items: Vec<usize> = (0..n).collect();
+            let all = check_counts(KPermutations::of(items, k), falling_factorial(n, k).unwrap());
+            all.iter().all(|p| p.len() == k && p.iter().collect::<HashSet<_>>().len() == k)
+                && all.windows(2).all(|pair| pair[0] < pair[1])
+        }
+
+        fn combination_counts(n: u8, k: u8) -> bool {
+            let (n, k) = (usize::from(n % 9), usize::from(k % 10));
+            let items: Vec<usize> = (0..n).collect();
+            let all = check_counts(Combinations::of(items, k), binomial(n, k).unwrap());
+            all.iter().all(|c| c.len() == k && c.windows(2).all(|pair| pair[0] < pair[1]))
+        }
+
+        fn multiset_counts(items: Vec<u8>) -> bool {
+            // Few distinct values, so that there are plenty of repeats.
+            let items: Vec<u8> = items.into_iter().take(7).map(|n| n % 3).collect();
+            let distinct: HashSet<Vec<u8>> = Permutations::of(items.clone()).collect();
+            let all = check_counts(MultisetPermutations::of(items), distinct.len());
+            all.iter().all(|p| distinct.contains(p))
+        }
+    }
+}
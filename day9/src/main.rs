@@ -72,6 +72,9 @@ fn execute(program: Vec<i64>, input: i64) {
         Ok(ProgramState::PendingInput(_)) => {
             eprintln!("Program waiting for input.");
         }
+        Ok(ProgramState::Breakpoint(_, breakpoint)) => {
+            eprintln!("Program stopped at {}.", breakpoint);
+        }
         Err(e) => {
             eprintln!("{}", e);
         }
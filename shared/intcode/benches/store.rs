@@ -0,0 +1,96 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use intcode::assembler::assemble;
+use intcode::{ExecutionMode, IntcodeMachine, ProgramState, ProgramStore};
+use std::collections::BTreeMap;
+use std::ops::{Index, IndexMut};
+
+// The original BTreeMap-backed store, kept here as a baseline.
+#[derive(Default)]
+struct BTreeStore(BTreeMap<usize, i64>);
+
+impl Index<usize> for BTreeStore {
+    type Output = i64;
+    fn index(&self, index: usize) -> &Self::Output {
+        self.0.get(&index).unwrap_or(&0)
+    }
+}
+
+impl IndexMut<usize> for BTreeStore {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        self.0.entry(index).or_insert(0)
+    }
+}
+
+const IMAGE_SIZE: usize = 1000;
+
+fn image() -> impl Iterator<Item = i64> {
+    (0..IMAGE_SIZE as i64).map(|n| n * 7 % 100)
+}
+
+fn exercise<S>(store: &mut S) -> i64
+where
+    S: Index<usize, Output = i64> + IndexMut<usize>,
+{
+    let mut total = 0;
+    for _ in 0..10 {
+        for address in 0..IMAGE_SIZE {
+            total += store[address];
+        }
+    }
+    for address in IMAGE_SIZE..IMAGE_SIZE + 500 {
+        store[address] = total;
+    }
+    for n in 0..100 {
+        store[1_000_000 + n * 1000] = n as i64;
+        total += store[1_000_000 + n * 1000];
+    }
+    total
+}
+
+fn store_access(c: &mut Criterion) {
+    let mut group = c.benchmark_group("store access");
+    group.bench_function("ProgramStore", |b| {
+        b.iter(|| {
+            let mut store: ProgramStore = image().collect();
+            black_box(exercise(&mut store))
+        })
+    });
+    group.bench_function("BTreeMap", |b| {
+        b.iter(|| {
+            let mut store = BTreeStore(image().enumerate().collect());
+            black_box(exercise(&mut store))
+        })
+    });
+    group.finish();
+}
+
+fn machine_run(c: &mut Criterion) {
+    let program = assemble(
+        "
+                IN [n]
+        loop:   ADD [total], [n], [total]
+                ADD [n], -1, [n]
+                JT [n], loop
+                OUT [total]
+                HLT
+        n:      DATA 0
+        total:  DATA 0
+        ",
+    )
+    .unwrap();
+
+    let mut group = c.benchmark_group("sum 1..=10000");
+    for &mode in &[ExecutionMode::Interpreted, ExecutionMode::Cached] {
+        group.bench_function(format!("{:?}", mode), |b| {
+            b.iter(|| {
+                let mut machine = IntcodeMachine::with_seed(program.clone(), 10_000);
+                machine.set_execution_mode(mode);
+                assert_eq!(machine.run(), Ok(ProgramState::Completed(vec![50_005_000])));
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, store_access, machine_run);
+criterion_main!(benches);
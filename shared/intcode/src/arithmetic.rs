@@ -0,0 +1,92 @@
+use crate::errors::ProgramError;
+use std::str::FromStr;
+
+// What `ADD`, `MUL` and `ARB` do when a result does not fit in an i64. The
+// same in debug and release builds.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Arithmetic {
+    #[default]
+    Wrapping,
+    Checked,
+    Saturating,
+}
+
+impl Arithmetic {
+    pub fn add(self, a: i64, b: i64) -> Result<i64, ProgramError> {
+        match self {
+            Arithmetic::Wrapping => Ok(a.wrapping_add(b)),
+            Arithmetic::Checked => a.checked_add(b).ok_or(ProgramError::Overflow(a, b)),
+            Arithmetic::Saturating => Ok(a.saturating_add(b)),
+        }
+    }
+
+    pub fn multiply(self, a: i64, b: i64) -> Result<i64, ProgramError> {
+        match self {
+            Arithmetic::Wrapping => Ok(a.wrapping_mul(b)),
+            Arithmetic::Checked => a.checked_mul(b).ok_or(ProgramError::Overflow(a, b)),
+            Arithmetic::Saturating => Ok(a.saturating_mul(b)),
+        }
+    }
+}
+
+impl FromStr for Arithmetic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wrapping" => Ok(Arithmetic::Wrapping),
+            "checked" => Ok(Arithmetic::Checked),
+            "saturating" => Ok(Arithmetic::Saturating),
+            other => Err(format!("Unknown arithmetic {}", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODES: [Arithmetic; 3] = [
+        Arithmetic::Wrapping,
+        Arithmetic::Checked,
+        Arithmetic::Saturating,
+    ];
+
+    #[test]
+    fn results_in_range_are_exact() {
+        for &mode in &MODES {
+            assert_eq!(
+                mode.add(1_125_899_906_842_624, 1),
+                Ok(1_125_899_906_842_625)
+            );
+            assert_eq!(
+                mode.multiply(34_915_192, 34_915_192),
+                Ok(1_219_070_632_396_864)
+            );
+            assert_eq!(mode.multiply(-3, i64::MAX / 3), Ok(i64::MIN + 2));
+        }
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(Arithmetic::Wrapping.add(i64::MAX, 1), Ok(i64::MIN));
+        assert_eq!(Arithmetic::Wrapping.multiply(i64::MIN, -1), Ok(i64::MIN));
+        assert_eq!(
+            Arithmetic::Checked.add(i64::MAX, 1),
+            Err(ProgramError::Overflow(i64::MAX, 1))
+        );
+        assert_eq!(
+            Arithmetic::Checked.multiply(i64::MIN, -1),
+            Err(ProgramError::Overflow(i64::MIN, -1))
+        );
+        assert_eq!(Arithmetic::Saturating.add(i64::MIN, -1), Ok(i64::MIN));
+        assert_eq!(Arithmetic::Saturating.multiply(i64::MAX, -2), Ok(i64::MIN));
+        assert_eq!(Arithmetic::Saturating.multiply(i64::MIN, -1), Ok(i64::MAX));
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("checked".parse(), Ok(Arithmetic::Checked));
+        assert!("modular".parse::<Arithmetic>().is_err());
+    }
+}
@@ -0,0 +1,221 @@
+use crate::errors::{ExecutionError, NonAsciiInput};
+use crate::{IntcodeMachine, ProgramState, StopReason};
+use std::fmt;
+use std::mem;
+
+const NEWLINE: i64 = 10;
+
+// Output from a program that talks in ASCII. Anything outside the ASCII
+// range, such as a final answer, is kept as a number.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AsciiOutput {
+    Text(String),
+    Value(i64),
+}
+
+impl fmt::Display for AsciiOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsciiOutput::Text(text) => write!(f, "{}", text),
+            AsciiOutput::Value(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+// Joins consecutive characters into runs of text.
+pub fn decode(values: &[i64]) -> Vec<AsciiOutput> {
+    let mut output = Vec::new();
+    let mut text = String::new();
+
+    for &value in values {
+        if (0..128).contains(&value) {
+            text.push(value as u8 as char);
+        } else {
+            if !text.is_empty() {
+                output.push(AsciiOutput::Text(mem::take(&mut text)));
+            }
+            output.push(AsciiOutput::Value(value));
+        }
+    }
+
+    if !text.is_empty() {
+        output.push(AsciiOutput::Text(text));
+    }
+    output
+}
+
+// Each character followed by a newline.
+pub fn encode(line: &str) -> Result<Vec<i64>, NonAsciiInput> {
+    if let Some(c) = line.chars().find(|c| !c.is_ascii()) {
+        return Err(NonAsciiInput(c));
+    }
+
+    Ok(line
+        .bytes()
+        .map(i64::from)
+        .chain(std::iter::once(NEWLINE))
+        .collect())
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AsciiRun {
+    pub output: Vec<AsciiOutput>,
+    pub stop: StopReason,
+}
+
+impl AsciiRun {
+    pub fn text(&self) -> String {
+        self.output
+            .iter()
+            .filter_map(|output| match output {
+                AsciiOutput::Text(text) => Some(text.as_str()),
+                AsciiOutput::Value(_) => None,
+            })
+            .collect()
+    }
+
+    pub fn values(&self) -> Vec<i64> {
+        self.output
+            .iter()
+            .filter_map(|output| match output {
+                AsciiOutput::Value(value) => Some(*value),
+                AsciiOutput::Text(_) => None,
+            })
+            .collect()
+    }
+}
+
+pub struct AsciiMachine {
+    machine: IntcodeMachine,
+}
+
+impl AsciiMachine {
+    pub fn new(machine: IntcodeMachine) -> AsciiMachine {
+        AsciiMachine { machine }
+    }
+
+    pub fn machine(&mut self) -> &mut IntcodeMachine {
+        &mut self.machine
+    }
+
+    pub fn into_inner(self) -> IntcodeMachine {
+        self.machine
+    }
+
+    // Queues a line of input. A trailing line ending is replaced by the
+    // single newline the program expects, so lines read from a terminal can
+    // be passed straight in.
+    pub fn send_line(&mut self, line: &str) -> Result<(), NonAsciiInput> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        self.machine.add_inputs(encode(line)?);
+        Ok(())
+    }
+
+    // Like `IntcodeMachine::run`, output made before an error is kept. It
+    // can be taken with `take_output`, to show what the program printed
+    // before it failed.
+    pub fn run(&mut self) -> Result<AsciiRun, ExecutionError> {
+        let (values, stop) = match self.machine.run()? {
+            ProgramState::Completed(values) => (values, StopReason::Halted),
+            ProgramState::PendingInput(values) => (values, StopReason::PendingInput),
+            ProgramState::Breakpoint(values, breakpoint) => {
+                (values, StopReason::Breakpoint(breakpoint))
+            }
+        };
+
+        Ok(AsciiRun {
+            output: decode(&values),
+            stop,
+        })
+    }
+
+    pub fn take_output(&mut self) -> Vec<AsciiOutput> {
+        decode(&mem::take(&mut self.machine.partial_outputs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::errors::ProgramError;
+
+    // Prompts with "> ", echoes a line, then reports its length.
+    fn echo() -> IntcodeMachine {
+        let program = assemble(
+            "
+                    OUT 62
+                    OUT 32
+            loop:   IN [c]
+                    OUT [c]
+                    ADD [length], 1, [length]
+                    EQ [c], 10, [done]
+                    JF [done], loop
+                    ADD [length], 999, [length]
+                    OUT [length]
+                    HLT
+            c:      DATA 0
+            done:   DATA 0
+            length: DATA 0
+            ",
+        )
+        .unwrap();
+        IntcodeMachine::new(program)
+    }
+
+    #[test]
+    fn decode_keeps_large_values() {
+        assert_eq!(
+            decode(&[72, 105, 10, 19_349_722, -1, 62]),
+            vec![
+                AsciiOutput::Text("Hi\n".to_string()),
+                AsciiOutput::Value(19_349_722),
+                AsciiOutput::Value(-1),
+                AsciiOutput::Text(">".to_string())
+            ]
+        );
+        assert_eq!(decode(&[]), vec![]);
+    }
+
+    #[test]
+    fn conversation() {
+        let mut machine = AsciiMachine::new(echo());
+        let run = machine.run().unwrap();
+        assert_eq!(run.stop, StopReason::PendingInput);
+        assert_eq!(run.text(), "> ");
+
+        machine.send_line("hello\r\n").unwrap();
+        let run = machine.run().unwrap();
+        assert_eq!(run.stop, StopReason::Halted);
+        assert_eq!(
+            run.output,
+            vec![
+                AsciiOutput::Text("hello\n".to_string()),
+                AsciiOutput::Value(1005)
+            ]
+        );
+        assert_eq!(run.values(), vec![1005]);
+    }
+
+    #[test]
+    fn output_before_an_error_is_kept() {
+        // Prints "ok" and a newline, then runs into an unknown opcode.
+        let mut machine =
+            AsciiMachine::new(IntcodeMachine::new(vec![104, 111, 104, 107, 104, 10, 0]));
+        let error = machine.run().unwrap_err();
+        assert_eq!(error.kind, ProgramError::UnknownOpcode(0));
+        assert_eq!(
+            machine.take_output(),
+            vec![AsciiOutput::Text("ok\n".to_string())]
+        );
+        assert_eq!(machine.take_output(), vec![]);
+    }
+
+    #[test]
+    fn non_ascii_input_is_rejected() {
+        let mut machine = AsciiMachine::new(echo());
+        assert_eq!(machine.send_line("héllo"), Err(NonAsciiInput('é')));
+        assert_eq!(machine.machine().pending_inputs().count(), 0);
+        assert_eq!(encode(""), Ok(vec![10]));
+    }
+}
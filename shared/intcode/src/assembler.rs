@@ -0,0 +1,515 @@
+use crate::errors::{AssemblyError, AssemblyErrorKind};
+use crate::instructions::{Argument, Instruction};
+use std::collections::HashMap;
+
+// Source syntax, one statement per line:
+//
+//   loop:   IN [x]                ; `[n]` or `[label]` is a position operand
+//           ADD [x], -1, [x]      ; a bare number or label is immediate
+//           OUT @rb+2             ; `@rb+n` / `@rb-n` is relative to the base
+//           JT [x], loop
+//           HLT
+//   x:      DATA 3, loop, 0       ; raw words
+//
+// Labels may carry an offset (`table+2`). Mnemonics are case insensitive.
+
+#[derive(Clone, Debug)]
+enum Value {
+    Number(i64),
+    Label {
+        name: String,
+        offset: i64,
+        column: usize,
+    },
+}
+
+#[derive(Clone, Debug)]
+enum Operand {
+    Position(Value),
+    Immediate(Value),
+    Relative(i64),
+}
+
+#[derive(Debug)]
+enum Statement {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<Operand>,
+    },
+    Data(Vec<Value>),
+}
+
+impl Statement {
+    fn size(&self) -> usize {
+        match self {
+            Statement::Instruction { operands, .. } => operands.len() + 1,
+            Statement::Data(values) => values.len(),
+        }
+    }
+}
+
+fn operand_count(mnemonic: &str) -> Option<usize> {
+    match mnemonic {
+        "ADD" | "MUL" | "LT" | "EQ" => Some(3),
+        "JT" | "JF" => Some(2),
+        "IN" | "OUT" | "ARB" => Some(1),
+        "HLT" => Some(0),
+        _ => None,
+    }
+}
+
+fn error(line: usize, column: usize, kind: AssemblyErrorKind) -> AssemblyError {
+    AssemblyError { line, column, kind }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+// Returns the text with surrounding whitespace removed, together with the
+// offset of its first character.
+fn trim(text: &str, offset: usize) -> (&str, usize) {
+    let leading = text.len() - text.trim_start().len();
+    (text.trim(), offset + leading)
+}
+
+fn parse_value(text: &str, line: usize, column: usize) -> Result<Value, AssemblyError> {
+    if let Ok(number) = text.parse::<i64>() {
+        return Ok(Value::Number(number));
+    }
+
+    let invalid = || {
+        error(
+            line,
+            column,
+            AssemblyErrorKind::InvalidOperand(text.to_string()),
+        )
+    };
+
+    let (name, offset) = match text.find(['+', '-']) {
+        Some(split) => {
+            let offset: i64 = text[split + 1..].trim().parse().map_err(|_| invalid())?;
+            let sign = if text[split..].starts_with('-') {
+                -1
+            } else {
+                1
+            };
+            (text[..split].trim(), sign * offset)
+        }
+        None => (text, 0),
+    };
+
+    if is_identifier(name) {
+        Ok(Value::Label {
+            name: name.to_string(),
+            offset,
+            column,
+        })
+    } else {
+        Err(invalid())
+    }
+}
+
+fn parse_operand(text: &str, line: usize, column: usize) -> Result<Operand, AssemblyError> {
+    let invalid = || {
+        error(
+            line,
+            column,
+            AssemblyErrorKind::InvalidOperand(text.to_string()),
+        )
+    };
+
+    if text.starts_with('[') {
+        if !text.ends_with(']') {
+            return Err(invalid());
+        }
+        let (inner, inner_column) = trim(&text[1..text.len() - 1], column + 1);
+        return parse_value(inner, line, inner_column).map(Operand::Position);
+    }
+
+    if let Some(rest) = text.strip_prefix("@rb") {
+        let rest = rest.replace(' ', "");
+        return match rest.as_str() {
+            "" => Ok(Operand::Relative(0)),
+            _ if rest.starts_with('+') => rest[1..]
+                .parse()
+                .map(Operand::Relative)
+                .map_err(|_| invalid()),
+            _ if rest.starts_with('-') => {
+                rest.parse().map(Operand::Relative).map_err(|_| invalid())
+            }
+            _ => Err(invalid()),
+        };
+    }
+
+    if text.is_empty() {
+        return Err(invalid());
+    }
+
+    parse_value(text, line, column).map(Operand::Immediate)
+}
+
+fn parse_list(text: &str, offset: usize) -> Vec<(&str, usize)> {
+    let mut items = Vec::new();
+    let mut start = 0;
+    for piece in text.split(',') {
+        items.push(trim(piece, offset + start));
+        start += piece.len() + 1;
+    }
+    items
+}
+
+fn parse_line(
+    text: &str,
+    line: usize,
+    labels: &mut Vec<(String, usize, usize)>,
+) -> Result<Option<Statement>, AssemblyError> {
+    let code = match text.find(';') {
+        Some(comment) => &text[..comment],
+        None => text,
+    };
+
+    let mut rest = code;
+    let mut offset = 0;
+    while let Some(colon) = rest.find(':') {
+        let (label, column) = trim(&rest[..colon], offset);
+        if !is_identifier(label) {
+            return Err(error(
+                line,
+                column + 1,
+                AssemblyErrorKind::InvalidLabel(label.to_string()),
+            ));
+        }
+        labels.push((label.to_string(), line, column + 1));
+        offset += colon + 1;
+        rest = &rest[colon + 1..];
+    }
+
+    let (body, body_offset) = trim(rest, offset);
+    if body.is_empty() {
+        return Ok(None);
+    }
+
+    let (word, operand_text) = match body.find(char::is_whitespace) {
+        Some(space) => (&body[..space], &body[space..]),
+        None => (body, ""),
+    };
+    let mnemonic = word.to_uppercase();
+    let operand_offset = body_offset + word.len();
+    let items = if operand_text.trim().is_empty() {
+        Vec::new()
+    } else {
+        parse_list(operand_text, operand_offset)
+    };
+
+    if mnemonic == "DATA" {
+        if items.is_empty() {
+            return Err(error(
+                line,
+                body_offset + 1,
+                AssemblyErrorKind::WrongOperandCount {
+                    expected: 1,
+                    found: 0,
+                },
+            ));
+        }
+        let values = items
+            .iter()
+            .map(|&(item, column)| parse_value(item, line, column + 1))
+            .collect::<Result<Vec<Value>, AssemblyError>>()?;
+        return Ok(Some(Statement::Data(values)));
+    }
+
+    let expected = operand_count(&mnemonic).ok_or_else(|| {
+        error(
+            line,
+            body_offset + 1,
+            AssemblyErrorKind::UnknownMnemonic(word.to_string()),
+        )
+    })?;
+
+    if items.len() != expected {
+        return Err(error(
+            line,
+            body_offset + 1,
+            AssemblyErrorKind::WrongOperandCount {
+                expected,
+                found: items.len(),
+            },
+        ));
+    }
+
+    let operands = items
+        .iter()
+        .map(|&(item, column)| parse_operand(item, line, column + 1))
+        .collect::<Result<Vec<Operand>, AssemblyError>>()?;
+
+    Ok(Some(Statement::Instruction { mnemonic, operands }))
+}
+
+fn resolve(
+    value: &Value,
+    line: usize,
+    symbols: &HashMap<String, usize>,
+) -> Result<i64, AssemblyError> {
+    match value {
+        Value::Number(n) => Ok(*n),
+        Value::Label {
+            name,
+            offset,
+            column,
+        } => match symbols.get(name) {
+            Some(&address) => (address as i64).checked_add(*offset).ok_or_else(|| {
+                error(
+                    line,
+                    *column,
+                    AssemblyErrorKind::OffsetOutOfRange(name.clone()),
+                )
+            }),
+            None => Err(error(
+                line,
+                *column,
+                AssemblyErrorKind::UndefinedLabel(name.clone()),
+            )),
+        },
+    }
+}
+
+fn build_instruction(mnemonic: &str, arguments: &[Argument]) -> Instruction {
+    match (mnemonic, arguments) {
+        ("ADD", &[a, b, c]) => Instruction::Add(a, b, c),
+        ("MUL", &[a, b, c]) => Instruction::Multiply(a, b, c),
+        ("LT", &[a, b, c]) => Instruction::LessThan(a, b, c),
+        ("EQ", &[a, b, c]) => Instruction::Equals(a, b, c),
+        ("JT", &[a, b]) => Instruction::JumpIfTrue(a, b),
+        ("JF", &[a, b]) => Instruction::JumpIfFalse(a, b),
+        ("IN", &[a]) => Instruction::Input(a),
+        ("OUT", &[a]) => Instruction::Output(a),
+        ("ARB", &[a]) => Instruction::SetRelativeBase(a),
+        ("HLT", &[]) => Instruction::Halt,
+        _ => unreachable!("operand count is checked while parsing"),
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Vec<i64>, AssemblyError> {
+    let mut statements = Vec::new();
+    let mut symbols = HashMap::new();
+    let mut address = 0;
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let mut labels = Vec::new();
+        let statement = parse_line(text, line, &mut labels)?;
+
+        for (name, line, column) in labels {
+            if symbols.insert(name.clone(), address).is_some() {
+                return Err(error(line, column, AssemblyErrorKind::DuplicateLabel(name)));
+            }
+        }
+
+        if let Some(statement) = statement {
+            address += statement.size();
+            statements.push((line, statement));
+        }
+    }
+
+    let mut program = Vec::with_capacity(address);
+    for (line, statement) in statements {
+        match statement {
+            Statement::Data(values) => {
+                for value in &values {
+                    program.push(resolve(value, line, &symbols)?);
+                }
+            }
+            Statement::Instruction { mnemonic, operands } => {
+                let arguments = operands
+                    .iter()
+                    .map(|operand| match operand {
+                        Operand::Position(value) => {
+                            resolve(value, line, &symbols).map(|n| Argument::Position(n as usize))
+                        }
+                        Operand::Immediate(value) => {
+                            resolve(value, line, &symbols).map(Argument::Immediate)
+                        }
+                        Operand::Relative(offset) => Ok(Argument::Relative(*offset)),
+                    })
+                    .collect::<Result<Vec<Argument>, AssemblyError>>()?;
+
+                program.extend(build_instruction(&mnemonic, &arguments).encode());
+            }
+        }
+    }
+
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::{disassemble, Line};
+    use crate::{IntcodeMachine, ProgramState, ProgramStore};
+
+    #[test]
+    fn assemble_multiply() {
+        assert_eq!(
+            assemble("MUL [4], 3, [4]\nDATA 33"),
+            Ok(vec![1002, 4, 3, 4, 33])
+        );
+    }
+
+    #[test]
+    fn assemble_relative_operands() {
+        let source = "
+            arb 1
+            out @rb-1
+            add @rb+2, @rb, [0]
+            hlt
+        ";
+        assert_eq!(
+            assemble(source),
+            Ok(vec![109, 1, 204, -1, 2201, 2, 0, 0, 99])
+        );
+    }
+
+    #[test]
+    fn labels_resolve_forwards_and_backwards() {
+        let source = "
+            start:  IN [counter]       ; read the loop count
+            loop:   OUT [counter]
+                    ADD [counter], -1, [counter]
+                    JT [counter], loop
+                    JF 0, end
+            counter: DATA 0
+            end:    HLT
+        ";
+        let program = assemble(source).unwrap();
+        assert_eq!(
+            program,
+            vec![3, 14, 4, 14, 1001, 14, -1, 14, 1005, 14, 2, 1106, 0, 15, 0, 99]
+        );
+
+        let mut machine = IntcodeMachine::with_seed(program, 3);
+        assert_eq!(machine.run(), Ok(ProgramState::Completed(vec![3, 2, 1])));
+    }
+
+    #[test]
+    fn data_directive_accepts_labels_with_offsets() {
+        let source = "
+            HLT
+            table: DATA table, table+2, end-1, -7
+            end: DATA 0
+        ";
+        assert_eq!(assemble(source), Ok(vec![99, 1, 3, 4, -7, 0]));
+    }
+
+    #[test]
+    fn disassembly_round_trips() {
+        let original = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        let program: ProgramStore = original.iter().cloned().collect();
+        let source: String = disassemble(&program)
+            .iter()
+            .map(|line| match line {
+                Line::Instruction { instruction, .. } => format!("{}\n", instruction),
+                Line::Data { value, .. } => format!("DATA {}\n", value),
+            })
+            .collect();
+
+        assert_eq!(assemble(&source), Ok(original));
+    }
+
+    #[test]
+    fn negative_positions_round_trip() {
+        let original = vec![1, -1, 0, 0, 99];
+        let program: ProgramStore = original.iter().cloned().collect();
+        let source: String = disassemble(&program)
+            .iter()
+            .map(|line| match line {
+                Line::Instruction { instruction, .. } => format!("{}\n", instruction),
+                Line::Data { value, .. } => format!("DATA {}\n", value),
+            })
+            .collect();
+
+        assert_eq!(source, "ADD [-1], [0], [0]\nHLT\n");
+        assert_eq!(assemble(&source), Ok(original));
+    }
+
+    #[test]
+    fn label_offsets_must_fit() {
+        assert_eq!(
+            assemble("HLT\nx: DATA x+9223372036854775807"),
+            Err(AssemblyError {
+                line: 2,
+                column: 9,
+                kind: AssemblyErrorKind::OffsetOutOfRange("x".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn unknown_mnemonic_reports_position() {
+        let result = assemble("HLT\n  label: FOO 1, 2");
+        assert_eq!(
+            result,
+            Err(AssemblyError {
+                line: 2,
+                column: 10,
+                kind: AssemblyErrorKind::UnknownMnemonic("FOO".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn bad_operand_reports_position() {
+        let result = assemble("ADD 1, [x, [2]");
+        assert_eq!(
+            result,
+            Err(AssemblyError {
+                line: 1,
+                column: 8,
+                kind: AssemblyErrorKind::InvalidOperand("[x".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn wrong_operand_count() {
+        let result = assemble("OUT 1, 2");
+        assert_eq!(
+            result.map_err(|e| e.kind),
+            Err(AssemblyErrorKind::WrongOperandCount {
+                expected: 1,
+                found: 2
+            })
+        );
+    }
+
+    #[test]
+    fn undefined_and_duplicate_labels() {
+        assert_eq!(
+            assemble("JT 1, nowhere"),
+            Err(AssemblyError {
+                line: 1,
+                column: 7,
+                kind: AssemblyErrorKind::UndefinedLabel("nowhere".to_string()),
+            })
+        );
+        assert_eq!(
+            assemble("a: HLT\na: HLT").map_err(|e| (e.line, e.kind)),
+            Err((2, AssemblyErrorKind::DuplicateLabel("a".to_string())))
+        );
+    }
+
+    #[test]
+    fn error_display() {
+        let error = assemble("\n\nXYZ").unwrap_err();
+        assert_eq!(error.to_string(), "Line 3, column 1: Unknown mnemonic XYZ");
+    }
+}
@@ -0,0 +1,472 @@
+use crate::errors::ExecutionError;
+use crate::io::{InputSource, OutputSink};
+use crate::{IntcodeMachine, StopReason};
+use std::collections::VecDeque;
+use std::future;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+// Polled for a value each time the program executes an input instruction.
+// `Poll::Pending` suspends the machine until the waker is woken, while
+// `Poll::Ready(None)` means no more input will ever come, and leaves the
+// machine waiting for input as `InputSource` does.
+pub trait AsyncInputSource {
+    fn poll_input(&mut self, cx: &mut Context<'_>) -> Poll<Option<i64>>;
+}
+
+impl AsyncInputSource for VecDeque<i64> {
+    fn poll_input(&mut self, _cx: &mut Context<'_>) -> Poll<Option<i64>> {
+        Poll::Ready(self.pop_front())
+    }
+}
+
+// Lets the synchronous machinery read from an async source, remembering
+// whether it had to wait.
+struct Polled<'a, 'b, I: ?Sized> {
+    source: &'a mut I,
+    cx: &'a mut Context<'b>,
+    pending: bool,
+}
+
+impl<'a, 'b, I: AsyncInputSource + ?Sized> InputSource for Polled<'a, 'b, I> {
+    fn next_input(&mut self) -> Option<i64> {
+        match self.source.poll_input(self.cx) {
+            Poll::Ready(value) => value,
+            Poll::Pending => {
+                self.pending = true;
+                None
+            }
+        }
+    }
+}
+
+impl IntcodeMachine {
+    // Polls `run_with` as a future. Note that the machine only yields to the
+    // executor while it waits for input.
+    pub fn poll_run<I, O>(
+        &mut self,
+        cx: &mut Context<'_>,
+        input: &mut I,
+        output: &mut O,
+    ) -> Poll<Result<StopReason, ExecutionError>>
+    where
+        I: AsyncInputSource + ?Sized,
+        O: OutputSink + ?Sized,
+    {
+        let mut input = Polled {
+            source: input,
+            cx,
+            pending: false,
+        };
+
+        match self.run_loop(&mut input, &mut SinkRef(output), None, None) {
+            Ok(Some(StopReason::PendingInput)) if input.pending => Poll::Pending,
+            Ok(Some(reason)) => Poll::Ready(Ok(reason)),
+            Ok(None) => unreachable!("No output limit was set"),
+            Err(e) => Poll::Ready(Err(e)),
+        }
+    }
+
+    pub async fn run_async<I, O>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+    ) -> Result<StopReason, ExecutionError>
+    where
+        I: AsyncInputSource + ?Sized,
+        O: OutputSink + ?Sized,
+    {
+        future::poll_fn(|cx| self.poll_run(cx, input, output)).await
+    }
+}
+
+// `run_loop` takes a `dyn OutputSink`, which an unsized `O` can't become.
+struct SinkRef<'a, O: ?Sized>(&'a mut O);
+
+impl<'a, O: OutputSink + ?Sized> OutputSink for SinkRef<'a, O> {
+    fn write_output(&mut self, value: i64) {
+        self.0.write_output(value);
+    }
+}
+
+// The machine's outputs as an async stream. The stream ends when the machine
+// stops for good: it halts, hits a breakpoint, or its input source is
+// exhausted; `stop_reason` then says which. It also ends after yielding an
+// error.
+pub struct AsyncOutputs<'a, I: ?Sized> {
+    machine: &'a mut IntcodeMachine,
+    input: &'a mut I,
+    finished: bool,
+    stop_reason: Option<StopReason>,
+}
+
+impl<'a, I: AsyncInputSource + ?Sized> AsyncOutputs<'a, I> {
+    pub fn new(machine: &'a mut IntcodeMachine, input: &'a mut I) -> AsyncOutputs<'a, I> {
+        AsyncOutputs {
+            machine,
+            input,
+            finished: false,
+            stop_reason: None,
+        }
+    }
+
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<i64, ExecutionError>>> {
+        if self.finished {
+            return Poll::Ready(None);
+        }
+
+        let mut input = Polled {
+            source: &mut *self.input,
+            cx,
+            pending: false,
+        };
+        let mut value = Vec::with_capacity(1);
+
+        match self.machine.run_loop(&mut input, &mut value, Some(1), None) {
+            Ok(None) => Poll::Ready(Some(Ok(value[0]))),
+            Ok(Some(StopReason::PendingInput)) if input.pending => Poll::Pending,
+            Ok(Some(reason)) => {
+                self.finished = true;
+                self.stop_reason = Some(reason);
+                Poll::Ready(None)
+            }
+            Err(e) => {
+                self.finished = true;
+                Poll::Ready(Some(Err(e)))
+            }
+        }
+    }
+
+    pub async fn next(&mut self) -> Option<Result<i64, ExecutionError>> {
+        future::poll_fn(|cx| self.poll_next(cx)).await
+    }
+
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.stop_reason
+    }
+
+    pub fn machine(&mut self) -> &mut IntcodeMachine {
+        self.machine
+    }
+}
+
+struct Shared {
+    queue: VecDeque<i64>,
+    waker: Option<Waker>,
+    senders: usize,
+    receiver_alive: bool,
+}
+
+// An unbounded single-consumer channel which doesn't depend on any
+// particular executor. The sender is an `OutputSink` and the receiver an
+// `AsyncInputSource`, so it can join one machine's output to another's
+// input.
+pub fn channel() -> (Sender, Receiver) {
+    let shared = Arc::new(Mutex::new(Shared {
+        queue: VecDeque::new(),
+        waker: None,
+        senders: 1,
+        receiver_alive: true,
+    }));
+
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+pub struct Sender {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Sender {
+    pub fn send(&self, value: i64) {
+        let waker = {
+            let mut shared = self.shared.lock().unwrap();
+            // Values sent after the receiver has gone are dropped.
+            if !shared.receiver_alive {
+                return;
+            }
+            shared.queue.push_back(value);
+            shared.waker.take()
+        };
+
+        // Woken outside the lock, in case the executor polls straight away.
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Clone for Sender {
+    fn clone(&self) -> Self {
+        self.shared.lock().unwrap().senders += 1;
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        let waker = {
+            let mut shared = self.shared.lock().unwrap();
+            shared.senders -= 1;
+            if shared.senders > 0 {
+                return;
+            }
+            shared.waker.take()
+        };
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl OutputSink for Sender {
+    fn write_output(&mut self, value: i64) {
+        self.send(value);
+    }
+}
+
+pub struct Receiver {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Receiver {
+    // Resolves to `None` once the queue is empty and every sender has gone.
+    pub async fn recv(&mut self) -> Option<i64> {
+        future::poll_fn(|cx| self.poll_input(cx)).await
+    }
+
+    pub fn try_recv(&mut self) -> Option<i64> {
+        self.shared.lock().unwrap().queue.pop_front()
+    }
+}
+
+impl AsyncInputSource for Receiver {
+    fn poll_input(&mut self, cx: &mut Context<'_>) -> Poll<Option<i64>> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.queue.pop_front() {
+            Some(value) => Poll::Ready(Some(value)),
+            None if shared.senders == 0 => Poll::Ready(None),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.receiver_alive = false;
+        shared.queue.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProgramError;
+    use crate::io::to_fn;
+    use std::cell::Cell;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::task::Wake;
+
+    // Echoes each input doubled, forever.
+    fn doubler() -> Vec<i64> {
+        vec![3, 11, 1002, 11, 2, 11, 4, 11, 1105, 1, 0, 0]
+    }
+
+    struct Flag(AtomicBool);
+
+    impl Wake for Flag {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    // A minimal single-threaded executor. Each round polls every unfinished
+    // future; if a round ends with nothing finished and nothing woken, no
+    // further progress is possible.
+    fn join_all<'a, T>(mut futures: Vec<Pin<Box<dyn Future<Output = T> + 'a>>>) -> Vec<T> {
+        let flag = Arc::new(Flag(AtomicBool::new(false)));
+        let waker = Waker::from(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut results: Vec<Option<T>> = futures.iter().map(|_| None).collect();
+
+        while results.iter().any(Option::is_none) {
+            flag.0.store(false, Ordering::SeqCst);
+            let mut finished = false;
+            for (future, result) in futures.iter_mut().zip(results.iter_mut()) {
+                if result.is_none() {
+                    if let Poll::Ready(value) = future.as_mut().poll(&mut cx) {
+                        *result = Some(value);
+                        finished = true;
+                    }
+                }
+            }
+            assert!(
+                finished || flag.0.load(Ordering::SeqCst),
+                "Executor stalled"
+            );
+        }
+
+        results.into_iter().map(Option::unwrap).collect()
+    }
+
+    fn block_on<'a, T>(future: impl Future<Output = T> + 'a) -> T {
+        join_all(vec![Box::pin(future)]).pop().unwrap()
+    }
+
+    #[test]
+    fn ready_input_runs_like_run_with() {
+        let mut machine = IntcodeMachine::new(doubler());
+        let mut input: VecDeque<i64> = vec![1, 2].into_iter().collect();
+        let mut outputs = Vec::new();
+        let result = block_on(machine.run_async(&mut input, &mut outputs));
+        assert_eq!(result, Ok(StopReason::PendingInput));
+        assert_eq!(outputs, vec![2, 4]);
+    }
+
+    #[test]
+    fn machine_waits_for_input_to_arrive() {
+        let (tx, mut rx) = channel();
+        let mut machine = IntcodeMachine::new(doubler());
+        let mut outputs = Vec::new();
+        let flag = Arc::new(Flag(AtomicBool::new(false)));
+        let waker = Waker::from(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        assert_eq!(
+            machine.poll_run(&mut cx, &mut rx, &mut outputs),
+            Poll::Pending
+        );
+        assert!(!flag.0.load(Ordering::SeqCst));
+
+        tx.send(21);
+        assert!(flag.0.load(Ordering::SeqCst));
+        assert_eq!(
+            machine.poll_run(&mut cx, &mut rx, &mut outputs),
+            Poll::Pending
+        );
+        assert_eq!(outputs, vec![42]);
+
+        drop(tx);
+        assert_eq!(
+            machine.poll_run(&mut cx, &mut rx, &mut outputs),
+            Poll::Ready(Ok(StopReason::PendingInput))
+        );
+    }
+
+    #[test]
+    fn amplifier_feedback_loop_runs_concurrently() {
+        // Day 7, part 2, first example.
+        let program = vec![
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1,
+            28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        let phases = [9, 8, 7, 6, 5];
+
+        let (senders, receivers): (Vec<Sender>, Vec<Receiver>) =
+            phases.iter().map(|_| channel()).unzip();
+        for (sender, &phase) in senders.iter().zip(phases.iter()) {
+            sender.send(phase);
+        }
+        senders[0].send(0);
+
+        let last_signal = Cell::new(0);
+        let mut futures: Vec<Pin<Box<dyn Future<Output = _>>>> = Vec::new();
+        for (n, mut input) in receivers.into_iter().enumerate() {
+            let next = senders[(n + 1) % phases.len()].clone();
+            let is_last = n == phases.len() - 1;
+            let last_signal = &last_signal;
+            let program = program.clone();
+            futures.push(Box::pin(async move {
+                let mut machine = IntcodeMachine::new(program);
+                let mut output = to_fn(|value| {
+                    if is_last {
+                        last_signal.set(value);
+                    }
+                    next.send(value);
+                });
+                machine.run_async(&mut input, &mut output).await
+            }));
+        }
+        drop(senders);
+
+        for result in join_all(futures) {
+            assert_eq!(result, Ok(StopReason::Halted));
+        }
+        assert_eq!(last_signal.get(), 139_629_729);
+    }
+
+    #[test]
+    fn output_stream_follows_input() {
+        let (tx, mut rx) = channel();
+        let mut machine = IntcodeMachine::new(doubler());
+
+        let consumer = async {
+            let mut outputs = machine.async_outputs(&mut rx);
+            let mut seen = Vec::new();
+            while let Some(value) = outputs.next().await {
+                seen.push(value);
+            }
+            (seen, outputs.stop_reason())
+        };
+        let producer = async move {
+            let mut tx = tx;
+            for n in 1..=3 {
+                tx.write_output(n);
+                future::poll_fn(yield_once()).await;
+            }
+        };
+
+        let results = join_all(vec![
+            Box::pin(async { Some(consumer.await) }),
+            Box::pin(async {
+                producer.await;
+                None
+            }),
+        ]);
+        assert_eq!(
+            results[0],
+            Some((vec![Ok(2), Ok(4), Ok(6)], Some(StopReason::PendingInput)))
+        );
+    }
+
+    #[test]
+    fn output_stream_ends_after_an_error() {
+        let mut machine = IntcodeMachine::new(vec![104, 7, 0]);
+        let mut input = VecDeque::new();
+        let mut outputs = AsyncOutputs::new(&mut machine, &mut input);
+        assert_eq!(block_on(outputs.next()), Some(Ok(7)));
+        assert_eq!(
+            block_on(outputs.next()).map(|result| result.map_err(|e| e.kind)),
+            Some(Err(ProgramError::UnknownOpcode(0)))
+        );
+        assert_eq!(block_on(outputs.next()), None);
+        assert_eq!(outputs.stop_reason(), None);
+    }
+
+    // Returns pending once, so that other futures get a turn.
+    fn yield_once() -> impl FnMut(&mut Context<'_>) -> Poll<()> {
+        let mut yielded = false;
+        move |cx| {
+            if yielded {
+                Poll::Ready(())
+            } else {
+                yielded = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+}
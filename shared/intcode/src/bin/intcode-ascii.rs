@@ -0,0 +1,59 @@
+use intcode::ascii::AsciiOutput;
+use intcode::{AsciiMachine, IntcodeMachine, StopReason};
+use std::io::{self, BufRead, Write};
+
+// Runs a program which talks in ASCII, with its text going to the terminal
+// and each line typed in going to the program.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: intcode-ascii <program file>");
+            std::process::exit(2);
+        }
+    };
+
+    let machine = IntcodeMachine::load(&path)?;
+    let mut machine = AsciiMachine::new(machine);
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+
+    loop {
+        let run = match machine.run() {
+            Ok(run) => run,
+            Err(e) => {
+                // Show whatever the program managed to print first.
+                show(&mut stdout.lock(), &machine.take_output())?;
+                return Err(e.into());
+            }
+        };
+        show(&mut stdout.lock(), &run.output)?;
+
+        match run.stop {
+            StopReason::Halted => return Ok(()),
+            StopReason::PendingInput => {}
+            StopReason::Breakpoint(_) => unreachable!("No breakpoints have been set"),
+        }
+
+        loop {
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            match machine.send_line(&line) {
+                Ok(()) => break,
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    }
+}
+
+fn show(stdout: &mut dyn Write, output: &[AsciiOutput]) -> io::Result<()> {
+    for output in output {
+        match output {
+            AsciiOutput::Text(text) => write!(stdout, "{}", text)?,
+            AsciiOutput::Value(value) => writeln!(stdout, "{}", value)?,
+        }
+    }
+    stdout.flush()
+}
@@ -0,0 +1,27 @@
+use intcode::control_flow::ControlFlowGraph;
+use intcode::IntcodeMachine;
+use std::process;
+
+// Prints a program's control-flow graph in Graphviz's DOT language, e.g.
+// `intcode-cfg program.txt | dot -Tsvg > program.svg`.
+fn main() {
+    let path = std::env::args().nth(1).unwrap_or_else(|| "-".to_string());
+    if path == "-h" || path == "--help" {
+        println!("Usage: intcode-cfg [program file]");
+        return;
+    }
+
+    let machine = IntcodeMachine::load(&path).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+
+    let graph = ControlFlowGraph::build(machine.memory());
+    print!("{}", graph.to_dot());
+    eprintln!(
+        "{} blocks, {} edges, {} unresolved jumps",
+        graph.blocks().count(),
+        graph.edges().len(),
+        graph.unresolved().len()
+    );
+}
@@ -0,0 +1,375 @@
+use intcode::disassembler::{disassemble_range, Line};
+use intcode::{Breakpoint, IntcodeMachine, ProgramState, StepStatus};
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+const HELP: &str = "\
+Commands:
+  s, step [n]             execute n instructions (default 1)
+  c, continue             run until halt, input is needed or a breakpoint
+  b, break <addr>         stop before executing the instruction at addr
+  b, break op <opcode>    stop before every instruction with this opcode
+                          (a number or a mnemonic such as IN)
+  watch <addr>            stop after any write to addr
+  rwatch <addr>           stop after any read from addr
+  d, delete <n>|all       delete breakpoint number n, or all of them
+  i, info                 list breakpoints, registers and queued input
+  r, regs                 show the instruction pointer and relative base
+  p, print <addr> [end]   print memory from addr up to (not including) end
+  in, input <v>...        queue input values
+  l, list [n]             disassemble n instructions around the current one
+  h, help                 show this message
+  q, quit                 exit the debugger";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Command {
+    Step(usize),
+    Continue,
+    Break(Breakpoint),
+    Delete(Option<usize>),
+    Info,
+    Registers,
+    Print(usize, usize),
+    Input(Vec<i64>),
+    List(usize),
+    Help,
+    Quit,
+}
+
+fn parse_number<T: FromStr>(text: Option<&str>, what: &str) -> Result<T, String> {
+    let text = text.ok_or_else(|| format!("Missing {}", what))?;
+    text.parse()
+        .map_err(|_| format!("Invalid {}: {}", what, text))
+}
+
+fn parse_opcode(text: Option<&str>) -> Result<i64, String> {
+    let text = text.ok_or_else(|| "Missing opcode".to_string())?;
+    let opcode = match text.to_uppercase().as_str() {
+        "ADD" => 1,
+        "MUL" => 2,
+        "IN" => 3,
+        "OUT" => 4,
+        "JT" => 5,
+        "JF" => 6,
+        "LT" => 7,
+        "EQ" => 8,
+        "ARB" => 9,
+        "HLT" => 99,
+        _ => text
+            .parse()
+            .map_err(|_| format!("Invalid opcode: {}", text))?,
+    };
+    Ok(opcode)
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return Err("Empty command".to_string()),
+        };
+
+        match command {
+            "s" | "step" => match words.next() {
+                Some(count) => parse_number(Some(count), "count").map(Command::Step),
+                None => Ok(Command::Step(1)),
+            },
+            "c" | "continue" => Ok(Command::Continue),
+            "b" | "break" => match words.next() {
+                Some("op") => parse_opcode(words.next())
+                    .map(|opcode| Command::Break(Breakpoint::Opcode(opcode))),
+                address => parse_number(address, "address")
+                    .map(|address| Command::Break(Breakpoint::Address(address))),
+            },
+            "watch" => parse_number(words.next(), "address")
+                .map(|address| Command::Break(Breakpoint::Write(address))),
+            "rwatch" => parse_number(words.next(), "address")
+                .map(|address| Command::Break(Breakpoint::Read(address))),
+            "d" | "delete" => match words.next() {
+                Some("all") => Ok(Command::Delete(None)),
+                number => parse_number(number, "breakpoint number")
+                    .map(|number| Command::Delete(Some(number))),
+            },
+            "i" | "info" => Ok(Command::Info),
+            "r" | "regs" => Ok(Command::Registers),
+            "p" | "print" => {
+                let start: usize = parse_number(words.next(), "address")?;
+                let end = match words.next() {
+                    Some(end) => parse_number(Some(end), "address")?,
+                    None => start.saturating_add(1),
+                };
+                Ok(Command::Print(start, end))
+            }
+            "in" | "input" => {
+                let values = words
+                    .map(|word| parse_number(Some(word), "input"))
+                    .collect::<Result<Vec<i64>, String>>()?;
+                Ok(Command::Input(values))
+            }
+            "l" | "list" => match words.next() {
+                Some(count) => parse_number(Some(count), "count").map(Command::List),
+                None => Ok(Command::List(5)),
+            },
+            "h" | "help" => Ok(Command::Help),
+            "q" | "quit" => Ok(Command::Quit),
+            other => Err(format!("Unknown command: {}", other)),
+        }
+    }
+}
+
+fn print_outputs(outputs: &[i64]) {
+    for output in outputs {
+        println!("output: {}", output);
+    }
+}
+
+fn print_registers(machine: &IntcodeMachine) {
+    println!(
+        "ip = {}, rb = {}",
+        machine.instruction_pointer(),
+        machine.relative_base()
+    );
+}
+
+fn list(machine: &IntcodeMachine, count: usize) {
+    let ip = machine.instruction_pointer();
+    let program = machine.memory();
+    // No listing needs more lines than the program has words.
+    let count = count.min(program.len().max(1));
+    let span = count.saturating_mul(4);
+
+    // Intcode cannot be decoded backwards, so sweep forwards from a little
+    // way before the current instruction and keep the last few lines.
+    let before: Vec<Line> = disassemble_range(program, ip.saturating_sub(span), ip)
+        .into_iter()
+        .filter(|line| line.address() + line.word_count() <= ip)
+        .collect();
+    let skip = before.len().saturating_sub(count / 2);
+    for line in &before[skip..] {
+        println!("   {}", line);
+    }
+
+    let after = disassemble_range(program, ip, ip.saturating_add(span));
+    for (index, line) in after.iter().take(count - count / 2).enumerate() {
+        let marker = if index == 0 { "=>" } else { "  " };
+        println!("{} {}", marker, line);
+    }
+}
+
+fn execute(machine: &mut IntcodeMachine, command: Command) -> bool {
+    match command {
+        Command::Step(count) => {
+            for _ in 0..count {
+                match machine.step() {
+                    Ok(step) => {
+                        println!("{:>6}: {}", step.address, step.instruction);
+                        if let Some(write) = step.write {
+                            println!("        [{}] <- {}", write.address, write.value);
+                        }
+                        if let Some(output) = step.output {
+                            println!("output: {}", output);
+                        }
+                        match step.status {
+                            StepStatus::Running => {}
+                            StepStatus::BlockedOnInput => {
+                                println!("Waiting for input.");
+                                break;
+                            }
+                            StepStatus::Halted => {
+                                println!("Program halted.");
+                                break;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+        Command::Continue => {
+            // Like gdb, don't stop again at the breakpoint we're sitting on.
+            machine.step_over_breakpoint();
+            match machine.run() {
+                Ok(ProgramState::Completed(outputs)) => {
+                    print_outputs(&outputs);
+                    println!("Program halted.");
+                }
+                Ok(ProgramState::PendingInput(outputs)) => {
+                    print_outputs(&outputs);
+                    println!("Waiting for input.");
+                }
+                Ok(ProgramState::Breakpoint(outputs, breakpoint)) => {
+                    print_outputs(&outputs);
+                    println!("Stopped at {}.", breakpoint);
+                    print_registers(machine);
+                }
+                Err(e) => println!("Error: {}", e),
+            }
+        }
+        Command::Break(breakpoint) => {
+            if machine.add_breakpoint(breakpoint) {
+                println!("Added {}.", breakpoint);
+            } else {
+                println!("There is already a {}.", breakpoint);
+            }
+        }
+        Command::Delete(None) => machine.clear_breakpoints(),
+        Command::Delete(Some(number)) => {
+            let breakpoint = machine.breakpoints().nth(number).cloned();
+            match breakpoint {
+                Some(breakpoint) => {
+                    machine.remove_breakpoint(&breakpoint);
+                    println!("Deleted {}.", breakpoint);
+                }
+                None => println!("No breakpoint number {}.", number),
+            }
+        }
+        Command::Info => {
+            for (number, breakpoint) in machine.breakpoints().enumerate() {
+                println!("{:>3}: {}", number, breakpoint);
+            }
+            print_registers(machine);
+            let inputs: Vec<String> = machine.pending_inputs().map(|v| v.to_string()).collect();
+            println!("queued input: [{}]", inputs.join(", "));
+        }
+        Command::Registers => print_registers(machine),
+        Command::Print(start, end) => {
+            for address in start..end {
+                println!("{:>6}: {}", address, machine.memory()[address]);
+            }
+        }
+        Command::Input(values) => machine.add_inputs(values),
+        Command::List(count) => list(machine, count.max(1)),
+        Command::Help => println!("{}", HELP),
+        Command::Quit => return false,
+    }
+
+    true
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: intcode-debugger <program file>");
+            std::process::exit(2);
+        }
+    };
+
+    let mut machine = IntcodeMachine::load(&path)?;
+    println!(
+        "Loaded {} ({} words). Type `help` for a list of commands.",
+        path,
+        machine.memory().len()
+    );
+
+    let stdin = io::stdin();
+    let mut previous = None;
+    loop {
+        print!("(icdb) ");
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            break;
+        }
+
+        // An empty line repeats the previous command, as in gdb.
+        let command = if line.trim().is_empty() {
+            match previous.clone() {
+                Some(command) => command,
+                None => continue,
+            }
+        } else {
+            match line.parse::<Command>() {
+                Ok(command) => command,
+                Err(e) => {
+                    println!("{}", e);
+                    continue;
+                }
+            }
+        };
+
+        previous = Some(command.clone());
+        if !execute(&mut machine, command) {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_breakpoints() {
+        assert_eq!("b 12".parse(), Ok(Command::Break(Breakpoint::Address(12))));
+        assert_eq!(
+            "break op in".parse(),
+            Ok(Command::Break(Breakpoint::Opcode(3)))
+        );
+        assert_eq!(
+            "break op 99".parse(),
+            Ok(Command::Break(Breakpoint::Opcode(99)))
+        );
+        assert_eq!("rwatch 7".parse(), Ok(Command::Break(Breakpoint::Read(7))));
+        assert_eq!("delete all".parse(), Ok(Command::Delete(None)));
+    }
+
+    #[test]
+    fn parse_other_commands() {
+        assert_eq!("s".parse(), Ok(Command::Step(1)));
+        assert_eq!("step 10".parse(), Ok(Command::Step(10)));
+        assert_eq!("p 4".parse(), Ok(Command::Print(4, 5)));
+        assert_eq!("print 4 8".parse(), Ok(Command::Print(4, 8)));
+        assert_eq!("input 1 -2 3".parse(), Ok(Command::Input(vec![1, -2, 3])));
+        assert!("step x".parse::<Command>().is_err());
+        assert!("frobnicate".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn commands_drive_the_machine() {
+        let mut machine = IntcodeMachine::new(vec![3, 7, 4, 7, 104, 5, 99, 0]);
+        assert!(execute(
+            &mut machine,
+            Command::Break(Breakpoint::Address(4))
+        ));
+        assert!(execute(&mut machine, Command::Input(vec![9])));
+        assert!(execute(&mut machine, Command::Continue));
+        assert_eq!(machine.instruction_pointer(), 4);
+        assert!(execute(&mut machine, Command::Step(2)));
+        assert_eq!(machine.instruction_pointer(), 6);
+        assert!(!execute(&mut machine, Command::Quit));
+    }
+
+    #[test]
+    fn continue_steps_over_the_current_breakpoint() {
+        let mut machine = IntcodeMachine::new(vec![3, 7, 4, 7, 104, 5, 99, 0]);
+        assert!(execute(
+            &mut machine,
+            Command::Break(Breakpoint::Address(2))
+        ));
+        assert!(execute(&mut machine, Command::Input(vec![9])));
+        assert!(execute(&mut machine, Command::Step(1)));
+        assert_eq!(machine.instruction_pointer(), 2);
+        assert!(execute(&mut machine, Command::Continue));
+        assert_eq!(machine.instruction_pointer(), 6);
+    }
+
+    #[test]
+    fn huge_addresses_and_counts() {
+        assert_eq!(
+            "p 18446744073709551615".parse(),
+            Ok(Command::Print(usize::MAX, usize::MAX))
+        );
+        let mut machine = IntcodeMachine::new(vec![99]);
+        assert!(execute(&mut machine, Command::List(usize::MAX)));
+    }
+}
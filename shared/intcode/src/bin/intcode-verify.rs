@@ -0,0 +1,36 @@
+use intcode::verifier::{verify, Severity};
+use intcode::IntcodeMachine;
+use std::process;
+
+// Checks a program without running it, and lists anything which looks
+// wrong. Exits with 1 if there were any errors, or 2 if the program could
+// not be read.
+fn main() {
+    let path = std::env::args().nth(1).unwrap_or_else(|| "-".to_string());
+    if path == "-h" || path == "--help" {
+        println!("Usage: intcode-verify [program file]");
+        return;
+    }
+
+    let machine = IntcodeMachine::load(&path).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+
+    let diagnostics = verify(machine.memory());
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic);
+    }
+
+    let count = |severity| {
+        diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity() == severity)
+            .count()
+    };
+    let errors = count(Severity::Error);
+    println!("{} errors, {} warnings", errors, count(Severity::Warning));
+    if errors > 0 {
+        process::exit(1);
+    }
+}
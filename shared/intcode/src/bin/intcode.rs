@@ -0,0 +1,467 @@
+use intcode::ascii;
+use intcode::{Arithmetic, ExecutionError, IntcodeMachine, ProgramError, StepStatus, Strictness};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "\
+Usage: intcode [options] [program file]
+
+Runs an Intcode program, read from the file or, if there is none or it is
+-, from standard input.
+
+Options:
+  -i, --input <values>        queue input values, separated by commas
+  -f, --input-file <path>     queue input values read from a file
+  -t, --interactive           read more input from the terminal when needed
+  -a, --ascii                 print output as text, and send typed lines as
+                              text; values above 127 are printed as numbers
+  -p, --patch <addr>=<value>  set a memory cell before running
+  -m, --max-instructions <n>  stop after executing n instructions
+      --timeout <seconds>     stop after running for this long
+      --max-cells <n>         fail if the program needs more than n memory
+                              cells
+  -l, --detect-loops          fail if the program gets stuck in a loop
+                              without input or output
+  -s, --strict                fail on immediate-mode writes, negative
+                              addresses and other invalid addressing
+      --arithmetic <mode>     what to do when a result overflows: wrapping
+                              (the default), checked or saturating
+      --peek <addr>           print a memory cell once the program stops
+  -h, --help                  show this message
+
+Exit status:
+  0  the program halted
+  1  the program failed with an error
+  2  bad command line, or the program could not be read
+  3  the program is waiting for input
+  4  the instruction limit or the timeout was reached";
+
+const EXIT_PROGRAM_ERROR: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_WAITING: i32 = 3;
+const EXIT_LIMIT: i32 = 4;
+
+#[derive(Debug, Default, Eq, PartialEq)]
+struct Options {
+    program: Option<String>,
+    inputs: Vec<i64>,
+    input_files: Vec<String>,
+    interactive: bool,
+    ascii: bool,
+    patches: Vec<(usize, i64)>,
+    peeks: Vec<usize>,
+    max_instructions: Option<u64>,
+    timeout: Option<Duration>,
+    max_cells: Option<usize>,
+    detect_loops: bool,
+    strict: bool,
+    arithmetic: Arithmetic,
+    help: bool,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Outcome {
+    Halted,
+    WaitingForInput,
+    LimitReached,
+    TimedOut,
+}
+
+impl Outcome {
+    fn exit_code(self) -> i32 {
+        match self {
+            Outcome::Halted => 0,
+            Outcome::WaitingForInput => EXIT_WAITING,
+            Outcome::LimitReached | Outcome::TimedOut => EXIT_LIMIT,
+        }
+    }
+}
+
+fn parse_values(text: &str) -> Result<Vec<i64>, String> {
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| format!("Invalid value: {}", value))
+        })
+        .collect()
+}
+
+fn parse_patch(text: &str) -> Result<(usize, i64), String> {
+    let invalid = || format!("Invalid patch {}, expected <addr>=<value>", text);
+    let mut parts = text.splitn(2, '=');
+    let address = parts.next().and_then(|a| a.trim().parse().ok());
+    let value = parts.next().and_then(|v| v.trim().parse().ok());
+    match (address, value) {
+        (Some(address), Some(value)) => Ok((address, value)),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_args<I>(mut args: I) -> Result<Options, String>
+where
+    I: Iterator<Item = String>,
+{
+    let mut options = Options::default();
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", name))
+        };
+
+        match arg.as_str() {
+            "-i" | "--input" => options.inputs.extend(parse_values(&value(&arg)?)?),
+            "-f" | "--input-file" => options.input_files.push(value(&arg)?),
+            "-t" | "--interactive" => options.interactive = true,
+            "-a" | "--ascii" => options.ascii = true,
+            "-p" | "--patch" => options.patches.push(parse_patch(&value(&arg)?)?),
+            "-m" | "--max-instructions" => {
+                let count = value(&arg)?;
+                let count = count
+                    .parse()
+                    .map_err(|_| format!("Invalid instruction count: {}", count))?;
+                options.max_instructions = Some(count);
+            }
+            "--timeout" => {
+                let seconds = value(&arg)?;
+                // Too long a timeout to add to the current time is rejected
+                // here, rather than left to fail once the program starts.
+                let timeout = seconds
+                    .parse()
+                    .ok()
+                    .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                    .filter(|&timeout| Instant::now().checked_add(timeout).is_some())
+                    .ok_or_else(|| format!("Invalid timeout: {}", seconds))?;
+                options.timeout = Some(timeout);
+            }
+            "--max-cells" => {
+                let count = value(&arg)?;
+                let count = count
+                    .parse()
+                    .map_err(|_| format!("Invalid cell count: {}", count))?;
+                options.max_cells = Some(count);
+            }
+            "-l" | "--detect-loops" => options.detect_loops = true,
+            "-s" | "--strict" => options.strict = true,
+            "--arithmetic" => options.arithmetic = value(&arg)?.parse()?,
+            "--peek" => {
+                let address = value(&arg)?;
+                let address = address
+                    .parse()
+                    .map_err(|_| format!("Invalid address: {}", address))?;
+                options.peeks.push(address);
+            }
+            "-h" | "--help" => options.help = true,
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("Unknown option {}", arg))
+            }
+            _ if options.program.is_some() => return Err(format!("Unexpected argument {}", arg)),
+            _ => options.program = Some(arg),
+        }
+    }
+
+    let program_from_stdin = options.program.as_deref().unwrap_or("-") == "-";
+    if options.interactive && program_from_stdin {
+        return Err("Interactive input needs the program to be read from a file".to_string());
+    }
+
+    Ok(options)
+}
+
+fn write_output(output: &mut dyn Write, value: i64, ascii: bool) -> io::Result<()> {
+    if ascii && (0..128).contains(&value) {
+        write!(output, "{}", value as u8 as char)
+    } else {
+        writeln!(output, "{}", value)
+    }
+}
+
+// Reads a line for a program which is waiting for input, returning `None`
+// at the end of the input.
+fn read_input(terminal: &mut dyn BufRead, ascii: bool) -> io::Result<Option<Vec<i64>>> {
+    loop {
+        let mut line = String::new();
+        if terminal.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let values = if ascii {
+            ascii::encode(line.trim_end_matches(['\r', '\n'])).map_err(|e| e.to_string())
+        } else {
+            parse_values(&line)
+        };
+        match values {
+            Ok(values) => return Ok(Some(values)),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum RunError {
+    Program(ExecutionError),
+    Io(io::Error),
+}
+
+impl From<io::Error> for RunError {
+    fn from(error: io::Error) -> Self {
+        RunError::Io(error)
+    }
+}
+
+// Steps the machine until it stops, printing each output as it happens.
+fn execute(
+    machine: &mut IntcodeMachine,
+    options: &Options,
+    mut terminal: Option<&mut dyn BufRead>,
+    output: &mut dyn Write,
+) -> Result<Outcome, RunError> {
+    let mut limits = machine.limits();
+    limits.max_instructions = options.max_instructions;
+    limits.max_cells = options.max_cells;
+    limits.deadline = options
+        .timeout
+        .and_then(|timeout| Instant::now().checked_add(timeout));
+    machine.set_limits(limits);
+    machine.set_loop_detection(options.detect_loops);
+
+    loop {
+        let step = match machine.step() {
+            Ok(step) => step,
+            Err(e) => match e.kind {
+                ProgramError::InstructionLimit(_) => return Ok(Outcome::LimitReached),
+                ProgramError::DeadlineExceeded => return Ok(Outcome::TimedOut),
+                _ => return Err(RunError::Program(e)),
+            },
+        };
+        if let Some(value) = step.output {
+            write_output(output, value, options.ascii)?;
+        }
+
+        match step.status {
+            StepStatus::Running => {}
+            StepStatus::Halted => return Ok(Outcome::Halted),
+            StepStatus::BlockedOnInput => {
+                output.flush()?;
+                let values = match terminal.as_mut() {
+                    Some(terminal) => read_input(*terminal, options.ascii)?,
+                    None => None,
+                };
+                match values {
+                    Some(values) => machine.add_inputs(values),
+                    None => return Ok(Outcome::WaitingForInput),
+                }
+            }
+        }
+    }
+}
+
+fn load(options: &Options) -> Result<IntcodeMachine, String> {
+    let path = options.program.as_deref().unwrap_or("-");
+    let mut machine = IntcodeMachine::load(path).map_err(|e| e.to_string())?;
+    if options.strict {
+        machine.set_strictness(Strictness::Strict);
+    }
+    machine.set_arithmetic(options.arithmetic);
+
+    for &(address, value) in &options.patches {
+        machine.poke(address, value);
+    }
+
+    machine.add_inputs(options.inputs.iter().cloned());
+    for path in &options.input_files {
+        let text = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+        machine.add_inputs(parse_values(&text)?);
+    }
+
+    Ok(machine)
+}
+
+fn main() {
+    let options = parse_args(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        process::exit(EXIT_USAGE);
+    });
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let mut machine = load(&options).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(EXIT_USAGE);
+    });
+
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+    let terminal: Option<&mut dyn BufRead> = if options.interactive {
+        Some(&mut stdin)
+    } else {
+        None
+    };
+
+    let stdout = io::stdout();
+    let result = execute(&mut machine, &options, terminal, &mut stdout.lock());
+    let _ = io::stdout().flush();
+
+    for &address in &options.peeks {
+        println!("[{}] = {}", address, machine.memory()[address]);
+    }
+
+    match result {
+        Ok(outcome) => {
+            match outcome {
+                Outcome::Halted => {}
+                Outcome::WaitingForInput => eprintln!("Waiting for input"),
+                Outcome::LimitReached => eprintln!("Instruction limit reached"),
+                Outcome::TimedOut => eprintln!("Timed out"),
+            }
+            process::exit(outcome.exit_code());
+        }
+        Err(RunError::Program(e)) => {
+            eprintln!("Error: {}", e);
+            process::exit(EXIT_PROGRAM_ERROR);
+        }
+        Err(RunError::Io(e)) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_USAGE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> impl Iterator<Item = String> + '_ {
+        line.split_whitespace().map(String::from)
+    }
+
+    fn run(program: Vec<i64>, options: &Options, typed: &str) -> (Outcome, String) {
+        let mut machine = IntcodeMachine::new(program);
+        machine.add_inputs(options.inputs.iter().cloned());
+        let mut terminal = typed.as_bytes();
+        let terminal: Option<&mut dyn BufRead> = if options.interactive {
+            Some(&mut terminal)
+        } else {
+            None
+        };
+        let mut output = Vec::new();
+        let outcome = execute(&mut machine, options, terminal, &mut output).unwrap();
+        (outcome, String::from_utf8(output).unwrap())
+    }
+
+    // Echoes each input doubled, forever.
+    fn doubler() -> Vec<i64> {
+        vec![3, 11, 1002, 11, 2, 11, 4, 11, 1105, 1, 0, 0]
+    }
+
+    #[test]
+    fn parse_options() {
+        let options = parse_args(args(
+            "-i 1,2 --input 3 -p 1=12 --patch 2=2 -m 100 --peek 0 -s --arithmetic checked day2.txt",
+        ))
+        .unwrap();
+        assert_eq!(
+            options,
+            Options {
+                program: Some("day2.txt".to_string()),
+                inputs: vec![1, 2, 3],
+                patches: vec![(1, 12), (2, 2)],
+                peeks: vec![0],
+                max_instructions: Some(100),
+                strict: true,
+                arithmetic: Arithmetic::Checked,
+                ..Options::default()
+            }
+        );
+
+        assert!(parse_args(args("--patch 12")).is_err());
+        assert!(parse_args(args("--input")).is_err());
+        assert!(parse_args(args("--frobnicate")).is_err());
+
+        let options = parse_args(args("--timeout 1.5 --max-cells 4096 -l")).unwrap();
+        assert_eq!(options.timeout, Some(Duration::from_millis(1500)));
+        assert_eq!(options.max_cells, Some(4096));
+        assert!(options.detect_loops);
+        assert!(parse_args(args("--timeout -1")).is_err());
+        assert!(parse_args(args("--timeout NaN")).is_err());
+        assert!(parse_args(args("--timeout 1e30")).is_err());
+        assert!(parse_args(args("--timeout inf")).is_err());
+        assert!(parse_args(args("a.txt b.txt")).is_err());
+        assert!(parse_args(args("--interactive")).is_err());
+        assert!(parse_args(args("--interactive -")).is_err());
+        assert!(parse_args(args("-t prog.txt")).unwrap().interactive);
+    }
+
+    #[test]
+    fn outcomes() {
+        let options = Options {
+            inputs: vec![4],
+            ..Options::default()
+        };
+        assert_eq!(
+            run(doubler(), &options, ""),
+            (Outcome::WaitingForInput, "8\n".to_string())
+        );
+        assert_eq!(
+            run(vec![104, 1, 99], &options, ""),
+            (Outcome::Halted, "1\n".to_string())
+        );
+
+        let options = Options {
+            max_instructions: Some(3),
+            ..Options::default()
+        };
+        assert_eq!(
+            run(vec![1105, 1, 0], &options, ""),
+            (Outcome::LimitReached, String::new())
+        );
+
+        let options = Options {
+            timeout: Some(Duration::from_millis(0)),
+            ..Options::default()
+        };
+        assert_eq!(
+            run(vec![1105, 1, 0], &options, ""),
+            (Outcome::TimedOut, String::new())
+        );
+    }
+
+    #[test]
+    fn interactive_input() {
+        let options = Options {
+            interactive: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            run(doubler(), &options, "1\nx\n2, 3\n"),
+            (Outcome::WaitingForInput, "2\n4\n6\n".to_string())
+        );
+
+        let options = Options {
+            interactive: true,
+            ascii: true,
+            ..Options::default()
+        };
+        // Echoes input back, until it halts on reading 0.
+        let echo = vec![3, 9, 4, 9, 1005, 9, 0, 99, 0, 0];
+        let (outcome, text) = run(echo, &options, "hi\n");
+        assert_eq!(outcome, Outcome::WaitingForInput);
+        assert_eq!(text, "hi\n");
+    }
+
+    #[test]
+    fn program_errors_are_reported() {
+        let mut machine = IntcodeMachine::new(vec![0]);
+        let result = execute(&mut machine, &Options::default(), None, &mut Vec::new());
+        match result {
+            Err(RunError::Program(e)) => assert_eq!(e.kind, ProgramError::UnknownOpcode(0)),
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+}
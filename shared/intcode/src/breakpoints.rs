@@ -0,0 +1,31 @@
+use std::fmt;
+
+// Address and opcode breakpoints stop the machine before the instruction
+// executes. Watchpoints stop it after the instruction which touched the cell.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Breakpoint {
+    Address(usize),
+    Opcode(i64),
+    Read(usize),
+    Write(usize),
+}
+
+impl Breakpoint {
+    pub fn is_watchpoint(&self) -> bool {
+        match self {
+            Breakpoint::Read(_) | Breakpoint::Write(_) => true,
+            Breakpoint::Address(_) | Breakpoint::Opcode(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Address(address) => write!(f, "breakpoint at address {}", address),
+            Breakpoint::Opcode(opcode) => write!(f, "breakpoint on opcode {}", opcode),
+            Breakpoint::Read(address) => write!(f, "read watchpoint on address {}", address),
+            Breakpoint::Write(address) => write!(f, "write watchpoint on address {}", address),
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use crate::errors::ProgramError;
+use crate::instructions::Instruction;
+use crate::ProgramStore;
+
+// The longest instruction is four words, so a write can only land inside an
+// instruction which starts at most three words earlier.
+const MAX_ARITY: usize = 4;
+
+// Cached instructions beyond the end of the dense region of memory are only
+// kept this close to it; anything further away is decoded every time.
+const CACHE_SLACK: usize = 1024;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ExecutionMode {
+    #[default]
+    Interpreted,
+    Cached,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DecodeCache {
+    entries: Vec<Option<Instruction>>,
+}
+
+impl DecodeCache {
+    pub fn new() -> DecodeCache {
+        DecodeCache {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn decode(
+        &mut self,
+        program: &ProgramStore,
+        address: usize,
+    ) -> Result<Instruction, ProgramError> {
+        if let Some(&Some(instruction)) = self.entries.get(address) {
+            return Ok(instruction);
+        }
+
+        let instruction = Instruction::read(program, address)?;
+        if address >= self.entries.len() && address < program.dense_len() + CACHE_SLACK {
+            self.entries.resize(address + 1, None);
+        }
+        if let Some(entry) = self.entries.get_mut(address) {
+            *entry = Some(instruction);
+        }
+        Ok(instruction)
+    }
+
+    // Drops every cached instruction with a word at `address`.
+    pub fn invalidate(&mut self, address: usize) {
+        let first = address.saturating_sub(MAX_ARITY - 1);
+        for start in first..=address {
+            if let Some(entry) = self.entries.get_mut(start) {
+                if let Some(instruction) = entry {
+                    if start + instruction.arity() > address {
+                        *entry = None;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instructions::Argument;
+
+    #[test]
+    fn decoded_instructions_are_reused() {
+        let mut program: ProgramStore = vec![1002, 4, 3, 4, 99].into_iter().collect();
+        let mut cache = DecodeCache::new();
+        let first = cache.decode(&program, 0).unwrap();
+
+        // Changing memory behind the cache's back shows that it was used.
+        program[0] = 99;
+        assert_eq!(cache.decode(&program, 0), Ok(first));
+    }
+
+    #[test]
+    fn writes_invalidate_overlapping_instructions() {
+        let mut program: ProgramStore = vec![1002, 4, 3, 4, 99].into_iter().collect();
+        let mut cache = DecodeCache::new();
+        cache.decode(&program, 0).unwrap();
+        cache.decode(&program, 4).unwrap();
+
+        program[2] = 5;
+        cache.invalidate(2);
+        assert_eq!(
+            cache.decode(&program, 0),
+            Ok(Instruction::Multiply(
+                Argument::Position(4),
+                Argument::Immediate(5),
+                Argument::Position(4)
+            ))
+        );
+        assert!(cache.entries[4].is_some());
+
+        cache.invalidate(4);
+        assert!(cache.entries[0].is_some());
+        assert!(cache.entries[4].is_none());
+    }
+
+    #[test]
+    fn decode_errors_are_not_cached() {
+        let mut program: ProgramStore = vec![0, 99].into_iter().collect();
+        let mut cache = DecodeCache::new();
+        assert_eq!(
+            cache.decode(&program, 0),
+            Err(ProgramError::UnknownOpcode(0))
+        );
+
+        program[0] = 99;
+        assert_eq!(cache.decode(&program, 0), Ok(Instruction::Halt));
+    }
+}
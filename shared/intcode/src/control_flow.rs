@@ -0,0 +1,277 @@
+use crate::instructions::{Exit, Instruction};
+use crate::ProgramStore;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+fn ends_block(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::JumpIfTrue(_, _) | Instruction::JumpIfFalse(_, _) | Instruction::Halt
+    )
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EdgeKind {
+    FallThrough,
+    // The target was an immediate operand.
+    Jump,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub instructions: Vec<(usize, Instruction)>,
+}
+
+impl BasicBlock {
+    pub fn last(&self) -> (usize, Instruction) {
+        self.instructions[self.instructions.len() - 1]
+    }
+}
+
+// The code reachable from address 0, split into basic blocks. Blocks start
+// at address 0, at jump targets and after jumps and halts, and are keyed by
+// their start address.
+//
+// Only jumps to immediate targets are followed. Those to targets in memory
+// are listed as unresolved, and code reached only through them is left
+// out. So are words which can't be decoded, and jumps out of the program;
+// the verifier reports both.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ControlFlowGraph {
+    blocks: BTreeMap<usize, BasicBlock>,
+    edges: Vec<Edge>,
+    unresolved: Vec<usize>,
+}
+
+impl ControlFlowGraph {
+    pub fn build(program: &ProgramStore) -> ControlFlowGraph {
+        let end = program.len();
+        let mut reachable = BTreeMap::new();
+        let mut leaders = BTreeSet::new();
+        let mut pending = vec![0];
+        leaders.insert(0);
+
+        while let Some(address) = pending.pop() {
+            if reachable.contains_key(&address) {
+                continue;
+            }
+            let instruction = match Instruction::read(program, address) {
+                Ok(instruction) => instruction,
+                Err(_) => continue,
+            };
+            reachable.insert(address, instruction);
+
+            for exit in instruction.exits(address) {
+                let target = match exit {
+                    Exit::FallThrough(next) if ends_block(&instruction) => {
+                        leaders.insert(next);
+                        next
+                    }
+                    Exit::FallThrough(next) => next,
+                    Exit::Jump(target) if target >= 0 && (target as usize) < end => {
+                        leaders.insert(target as usize);
+                        target as usize
+                    }
+                    Exit::Jump(_) | Exit::Indirect => continue,
+                };
+                if target < end {
+                    pending.push(target);
+                }
+            }
+        }
+
+        let mut graph = ControlFlowGraph::default();
+        for &start in leaders.iter().filter(|start| reachable.contains_key(start)) {
+            let mut block = BasicBlock {
+                start,
+                instructions: Vec::new(),
+            };
+            let mut address = start;
+            while let Some(&instruction) = reachable.get(&address) {
+                block.instructions.push((address, instruction));
+                address += instruction.arity();
+                if ends_block(&instruction) || leaders.contains(&address) {
+                    break;
+                }
+            }
+            graph.blocks.insert(start, block);
+        }
+
+        for block in graph.blocks.values() {
+            let (address, instruction) = block.last();
+            for exit in instruction.exits(address) {
+                let (to, kind) = match exit {
+                    Exit::FallThrough(next) => (next, EdgeKind::FallThrough),
+                    Exit::Jump(target) if target >= 0 => (target as usize, EdgeKind::Jump),
+                    Exit::Jump(_) => continue,
+                    Exit::Indirect => {
+                        graph.unresolved.push(address);
+                        continue;
+                    }
+                };
+                if graph.blocks.contains_key(&to) {
+                    graph.edges.push(Edge {
+                        from: block.start,
+                        to,
+                        kind,
+                    });
+                }
+            }
+        }
+
+        graph
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = &BasicBlock> {
+        self.blocks.values()
+    }
+
+    pub fn block(&self, start: usize) -> Option<&BasicBlock> {
+        self.blocks.get(&start)
+    }
+
+    pub fn edges(&self) -> &[Edge] {
+        &self.edges
+    }
+
+    // The addresses of jumps whose targets come from memory.
+    pub fn unresolved(&self) -> &[usize] {
+        &self.unresolved
+    }
+
+    // The graph in Graphviz's DOT language. Jumps are drawn solid and fall
+    // throughs dashed, and unresolved jumps lead to a node marked "?".
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph intcode {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+
+        for block in self.blocks.values() {
+            let label: String = block
+                .instructions
+                .iter()
+                .map(|(address, instruction)| format!("{}: {}\\l", address, instruction))
+                .collect();
+            writeln!(dot, "    b{} [label=\"{}\"];", block.start, label).unwrap();
+        }
+
+        for edge in &self.edges {
+            let style = match edge.kind {
+                EdgeKind::FallThrough => "dashed",
+                EdgeKind::Jump => "solid",
+            };
+            writeln!(dot, "    b{} -> b{} [style={}];", edge.from, edge.to, style).unwrap();
+        }
+
+        if !self.unresolved.is_empty() {
+            writeln!(dot, "    unresolved [label=\"?\", shape=circle];").unwrap();
+            for &address in &self.unresolved {
+                let block = self
+                    .blocks
+                    .values()
+                    .find(|block| block.last().0 == address)
+                    .map_or(address, |block| block.start);
+                writeln!(dot, "    b{} -> unresolved [style=dotted];", block).unwrap();
+            }
+        }
+
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    fn graph(source: &str) -> ControlFlowGraph {
+        ControlFlowGraph::build(&assemble(source).unwrap().into_iter().collect())
+    }
+
+    fn starts(graph: &ControlFlowGraph) -> Vec<usize> {
+        graph.blocks().map(|block| block.start).collect()
+    }
+
+    #[test]
+    fn loop_is_split_into_blocks() {
+        let graph = graph(
+            "
+                    IN [n]
+            loop:   OUT [n]
+                    ADD [n], -1, [n]
+                    JT [n], loop
+                    HLT
+            n:      DATA 0
+            ",
+        );
+
+        assert_eq!(starts(&graph), vec![0, 2, 11]);
+        assert_eq!(graph.block(2).unwrap().instructions.len(), 3);
+        assert_eq!(
+            graph.edges(),
+            &[
+                Edge {
+                    from: 0,
+                    to: 2,
+                    kind: EdgeKind::FallThrough
+                },
+                Edge {
+                    from: 2,
+                    to: 2,
+                    kind: EdgeKind::Jump
+                },
+                Edge {
+                    from: 2,
+                    to: 11,
+                    kind: EdgeKind::FallThrough
+                },
+            ]
+        );
+        assert!(graph.unresolved().is_empty());
+    }
+
+    #[test]
+    fn indirect_jumps_are_unresolved() {
+        // A call through a return address held in memory.
+        let graph = graph(
+            "
+                    ADD back, 0, [return]
+                    JT 1, function
+            back:   HLT
+            function: OUT 1
+                    JT 1, [return]
+            return: DATA 0
+            ",
+        );
+
+        assert_eq!(starts(&graph), vec![0, 8]);
+        assert_eq!(graph.unresolved(), &[10]);
+        // The return to `back` can't be seen without running it.
+        assert_eq!(graph.edges().len(), 1);
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph intcode {\n"));
+        assert!(dot.contains("    b0 [label=\"0: ADD 7, 0, [13]\\l4: JT 1, 8\\l\"];\n"));
+        assert!(dot.contains("    b0 -> b8 [style=solid];\n"));
+        assert!(dot.contains("    b8 -> unresolved [style=dotted];\n"));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn undecodable_words_are_left_out() {
+        let program: ProgramStore = vec![1105, 1, 4, 77, 104, 1, 42].into_iter().collect();
+        let graph = ControlFlowGraph::build(&program);
+        assert_eq!(starts(&graph), vec![0, 4]);
+        assert_eq!(graph.block(4).unwrap().instructions.len(), 1);
+        assert_eq!(graph.edges().len(), 1);
+    }
+}
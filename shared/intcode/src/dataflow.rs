@@ -0,0 +1,250 @@
+use crate::errors::DataflowError;
+use crate::{IntcodeMachine, ProgramState};
+use std::collections::VecDeque;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct NodeId(usize);
+
+struct Node {
+    name: String,
+    machine: IntcodeMachine,
+    outputs: Vec<i64>,
+    halted: bool,
+}
+
+struct Edge {
+    name: String,
+    from: NodeId,
+    to: NodeId,
+    queue: VecDeque<i64>,
+    carried: Vec<i64>,
+}
+
+// Machines joined by edges which carry every output of one machine to the
+// input of another. A machine with several outgoing edges sends a copy of
+// each output down all of them; one with several incoming edges reads from
+// them in the order they were connected. Any topology is allowed,
+// including cycles.
+#[derive(Default)]
+pub struct Dataflow {
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+}
+
+impl Dataflow {
+    pub fn new() -> Dataflow {
+        Dataflow {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+        }
+    }
+
+    // `seed` is queued as the machine's first input, ahead of anything
+    // arriving along edges; for an amplifier, its phase setting.
+    pub fn add_node<S>(&mut self, name: &str, program: &[i64], seed: S) -> NodeId
+    where
+        S: IntoIterator<Item = i64>,
+    {
+        let mut machine = IntcodeMachine::new(program.to_vec());
+        machine.add_inputs(seed);
+        self.nodes.push(Node {
+            name: name.to_string(),
+            machine,
+            outputs: Vec::new(),
+            halted: false,
+        });
+        NodeId(self.nodes.len() - 1)
+    }
+
+    pub fn connect(&mut self, name: &str, from: NodeId, to: NodeId) {
+        self.edges.push(Edge {
+            name: name.to_string(),
+            from,
+            to,
+            queue: VecDeque::new(),
+            carried: Vec::new(),
+        });
+    }
+
+    // Gives a node input from outside the graph.
+    pub fn send(&mut self, node: NodeId, value: i64) {
+        self.nodes[node.0].machine.add_input(value);
+    }
+
+    pub fn name(&self, node: NodeId) -> &str {
+        &self.nodes[node.0].name
+    }
+
+    pub fn machine(&self, node: NodeId) -> &IntcodeMachine {
+        &self.nodes[node.0].machine
+    }
+
+    // Everything the node has output so far.
+    pub fn outputs(&self, node: NodeId) -> &[i64] {
+        &self.nodes[node.0].outputs
+    }
+
+    pub fn is_halted(&self, node: NodeId) -> bool {
+        self.nodes[node.0].halted
+    }
+
+    // Every value sent along the named edge so far.
+    pub fn edge_values(&self, name: &str) -> Option<&[i64]> {
+        self.edges
+            .iter()
+            .find(|edge| edge.name == name)
+            .map(|edge| edge.carried.as_slice())
+    }
+
+    // Runs each machine in turn until `sink` halts, and returns everything
+    // it output. If a full round passes in which no machine moves, every
+    // machine left is waiting for input that will never come.
+    pub fn run_until_halted(&mut self, sink: NodeId) -> Result<&[i64], DataflowError> {
+        while !self.nodes[sink.0].halted {
+            if !self.round()? {
+                let waiting = self
+                    .nodes
+                    .iter()
+                    .filter(|node| !node.halted)
+                    .map(|node| node.name.clone())
+                    .collect();
+                return Err(DataflowError::Deadlock(waiting));
+            }
+        }
+
+        Ok(&self.nodes[sink.0].outputs)
+    }
+
+    // Gives every running machine one turn, and reports whether anything
+    // happened.
+    fn round(&mut self) -> Result<bool, DataflowError> {
+        let mut progress = false;
+
+        for index in 0..self.nodes.len() {
+            if self.nodes[index].halted {
+                continue;
+            }
+
+            for edge in self.edges.iter_mut().filter(|edge| edge.to.0 == index) {
+                if !edge.queue.is_empty() {
+                    progress = true;
+                    self.nodes[index].machine.add_inputs(edge.queue.drain(..));
+                }
+            }
+
+            let node = &mut self.nodes[index];
+            let start = node.machine.instruction_pointer();
+            let outputs = match node.machine.run() {
+                Ok(ProgramState::Completed(outputs)) => {
+                    node.halted = true;
+                    progress = true;
+                    outputs
+                }
+                Ok(ProgramState::PendingInput(outputs)) => outputs,
+                Ok(ProgramState::Breakpoint(_, _)) => unreachable!("No breakpoints have been set"),
+                Err(error) => {
+                    return Err(DataflowError::Program {
+                        node: node.name.clone(),
+                        error,
+                    })
+                }
+            };
+
+            if node.machine.instruction_pointer() != start || !outputs.is_empty() {
+                progress = true;
+            }
+            node.outputs.extend(&outputs);
+
+            for edge in self.edges.iter_mut().filter(|edge| edge.from.0 == index) {
+                edge.queue.extend(&outputs);
+                edge.carried.extend(&outputs);
+            }
+        }
+
+        Ok(progress)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProgramError;
+
+    // Multiplies each input by `factor`, and halts after `count` of them.
+    fn multiplier(factor: i64, count: i64) -> Vec<i64> {
+        vec![
+            3, 17, 1002, 17, factor, 17, 4, 17, 1001, 18, -1, 18, 1005, 18, 0, 99, 0, 0, count,
+        ]
+    }
+
+    // Outputs the sum of each pair of inputs, forever.
+    fn adder() -> Vec<i64> {
+        vec![3, 13, 3, 14, 1, 13, 14, 13, 4, 13, 1105, 1, 0, 0, 0]
+    }
+
+    #[test]
+    fn fan_out_and_fan_in() {
+        let mut graph = Dataflow::new();
+        let source = graph.add_node("source", &multiplier(1, 2), vec![]);
+        let double = graph.add_node("double", &multiplier(2, 2), vec![]);
+        let triple = graph.add_node("triple", &multiplier(3, 2), vec![]);
+        let sum = graph.add_node("sum", &adder(), vec![]);
+        let limit = graph.add_node("limit", &multiplier(1, 2), vec![]);
+        graph.connect("s-d", source, double);
+        graph.connect("s-t", source, triple);
+        graph.connect("d-sum", double, sum);
+        graph.connect("t-sum", triple, sum);
+        graph.connect("sum-limit", sum, limit);
+        graph.send(source, 1);
+        graph.send(source, 10);
+
+        // double sends 2 and 20 before triple is run, so sum reads 2 + 20.
+        assert_eq!(graph.run_until_halted(limit), Ok(&[22, 33][..]));
+        assert_eq!(graph.edge_values("s-t"), Some(&[1, 10][..]));
+        assert_eq!(graph.edge_values("d-sum"), Some(&[2, 20][..]));
+        assert!(graph.is_halted(source));
+        assert!(!graph.is_halted(sum));
+    }
+
+    #[test]
+    fn cycles_run_until_the_sink_halts() {
+        // Each pass round the loop doubles the value, five times over.
+        let mut graph = Dataflow::new();
+        let first = graph.add_node("first", &multiplier(1, 5), vec![1]);
+        let second = graph.add_node("second", &multiplier(2, 5), vec![]);
+        graph.connect("forward", first, second);
+        graph.connect("back", second, first);
+
+        assert_eq!(graph.run_until_halted(second), Ok(&[2, 4, 8, 16, 32][..]));
+    }
+
+    #[test]
+    fn deadlock_is_reported() {
+        let mut graph = Dataflow::new();
+        let a = graph.add_node("a", &multiplier(1, 5), vec![]);
+        let b = graph.add_node("b", &multiplier(1, 5), vec![]);
+        graph.connect("ab", a, b);
+        graph.connect("ba", b, a);
+
+        assert_eq!(
+            graph.run_until_halted(b),
+            Err(DataflowError::Deadlock(vec![
+                "a".to_string(),
+                "b".to_string()
+            ]))
+        );
+    }
+
+    #[test]
+    fn program_errors_name_the_node() {
+        let mut graph = Dataflow::new();
+        let bad = graph.add_node("bad", &[0], vec![]);
+        match graph.run_until_halted(bad) {
+            Err(DataflowError::Program { node, error }) => {
+                assert_eq!(node, "bad");
+                assert_eq!(error.kind, ProgramError::UnknownOpcode(0));
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+}
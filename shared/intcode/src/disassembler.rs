@@ -0,0 +1,178 @@
+use crate::instructions::Instruction;
+use crate::ProgramStore;
+use std::fmt;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Line {
+    Instruction {
+        address: usize,
+        words: Vec<i64>,
+        instruction: Instruction,
+    },
+    Data {
+        address: usize,
+        value: i64,
+    },
+}
+
+impl Line {
+    pub fn address(&self) -> usize {
+        match *self {
+            Line::Instruction { address, .. } | Line::Data { address, .. } => address,
+        }
+    }
+
+    pub fn word_count(&self) -> usize {
+        match self {
+            Line::Instruction { words, .. } => words.len(),
+            Line::Data { .. } => 1,
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Line::Instruction {
+                address,
+                words,
+                instruction,
+            } => {
+                let raw = words
+                    .iter()
+                    .map(|word| word.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" ");
+                write!(f, "{:>6}: {:<32} {}", address, raw, instruction)
+            }
+            Line::Data { address, value } => {
+                write!(f, "{:>6}: {:<32} DATA {}", address, value, value)
+            }
+        }
+    }
+}
+
+pub fn disassemble(program: &ProgramStore) -> Vec<Line> {
+    disassemble_range(program, 0, program.len())
+}
+
+// Linear sweep from `start`. Instructions may extend past `end`, but anything
+// which cannot be decoded, or which would run off the end of the loaded
+// image, is shown as a single word of data.
+pub fn disassemble_range(program: &ProgramStore, start: usize, end: usize) -> Vec<Line> {
+    let limit = program.len().max(end);
+    let mut lines = Vec::new();
+    let mut address = start;
+
+    while address < end {
+        let line = match Instruction::read(program, address) {
+            Ok(instruction) if address + instruction.arity() <= limit => {
+                let words = (address..address + instruction.arity())
+                    .map(|index| program[index])
+                    .collect();
+                Line::Instruction {
+                    address,
+                    words,
+                    instruction,
+                }
+            }
+            _ => Line::Data {
+                address,
+                value: program[address],
+            },
+        };
+
+        address += line.word_count();
+        lines.push(line);
+    }
+
+    lines
+}
+
+pub fn listing(program: &ProgramStore) -> String {
+    disassemble(program)
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instructions::Argument;
+
+    #[test]
+    fn disassemble_simple_program() {
+        let program: ProgramStore = vec![1002, 4, 3, 4, 33].into_iter().collect();
+        let lines = disassemble(&program);
+        assert_eq!(
+            lines,
+            vec![
+                Line::Instruction {
+                    address: 0,
+                    words: vec![1002, 4, 3, 4],
+                    instruction: Instruction::Multiply(
+                        Argument::Position(4),
+                        Argument::Immediate(3),
+                        Argument::Position(4)
+                    ),
+                },
+                Line::Data {
+                    address: 4,
+                    value: 33
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn relative_operands_are_rendered() {
+        let program: ProgramStore = vec![109, 1, 204, -1, 99].into_iter().collect();
+        let text: Vec<String> = disassemble(&program)
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+        assert!(text[0].ends_with("ARB 1"));
+        assert!(text[1].ends_with("OUT @rb-1"));
+        assert!(text[2].ends_with("HLT"));
+    }
+
+    #[test]
+    fn unknown_opcodes_become_data() {
+        let program: ProgramStore = vec![0, 33, 99].into_iter().collect();
+        let lines = disassemble(&program);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            Line::Data {
+                address: 0,
+                value: 0
+            }
+        );
+        assert_eq!(
+            lines[1],
+            Line::Data {
+                address: 1,
+                value: 33
+            }
+        );
+        assert_eq!(
+            lines[2].to_string().trim_end(),
+            "     2: 99                               HLT"
+        );
+    }
+
+    #[test]
+    fn truncated_instruction_becomes_data() {
+        let program: ProgramStore = vec![99, 1, 0].into_iter().collect();
+        let lines = disassemble(&program);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[1],
+            Line::Data {
+                address: 1,
+                value: 1
+            }
+        );
+    }
+}
@@ -1,5 +1,8 @@
+use crate::network::Packet;
 use std::error::Error;
 use std::fmt;
+use std::io;
+use std::num::ParseIntError;
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum ProgramError {
@@ -7,6 +10,17 @@ pub enum ProgramError {
     IndexOutOfRange(usize),
     InsufficientInput,
     UnknownParameterMode(i64),
+    ImmediateDestination(i64),
+    NegativeAddress(i64),
+    UnusedParameterMode(i64),
+    NegativeJump(i64),
+    Overflow(i64, i64),
+    InstructionLimit(u64),
+    MemoryLimit(usize),
+    AddressLimit(usize),
+    OutputLimit(usize),
+    DeadlineExceeded,
+    InfiniteLoop { entry: usize, period: u64 },
 }
 
 impl fmt::Display for ProgramError {
@@ -16,8 +30,283 @@ impl fmt::Display for ProgramError {
             ProgramError::IndexOutOfRange(index) => write!(f, "Index out of range: {}", index),
             ProgramError::InsufficientInput => write!(f, "Not enough input values supplied"),
             ProgramError::UnknownParameterMode(mode) => write!(f, "Unknown parameter mode {}", mode),
+            ProgramError::ImmediateDestination(value) => {
+                write!(f, "Immediate-mode destination {}", value)
+            }
+            ProgramError::NegativeAddress(address) => write!(f, "Negative address {}", address),
+            ProgramError::UnusedParameterMode(word) => {
+                write!(f, "Mode given for a missing parameter in {}", word)
+            }
+            ProgramError::NegativeJump(target) => write!(f, "Jump to negative address {}", target),
+            ProgramError::Overflow(a, b) => write!(f, "Arithmetic overflow on {} and {}", a, b),
+            ProgramError::InstructionLimit(limit) => {
+                write!(f, "Reached the limit of {} instructions", limit)
+            }
+            ProgramError::MemoryLimit(limit) => write!(f, "Reached the limit of {} cells", limit),
+            ProgramError::AddressLimit(address) => {
+                write!(f, "Address {} is beyond the address limit", address)
+            }
+            ProgramError::OutputLimit(limit) => {
+                write!(f, "Reached the limit of {} buffered outputs", limit)
+            }
+            ProgramError::DeadlineExceeded => write!(f, "Deadline exceeded"),
+            ProgramError::InfiniteLoop { entry, period } => write!(
+                f,
+                "Infinite loop entered at {}, repeating every {} instructions",
+                entry, period
+            ),
         }
     }
 }
 
-impl Error for ProgramError {}
\ No newline at end of file
+impl Error for ProgramError {}
+
+// Where the machine was when an error happened.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ErrorContext {
+    pub instruction_pointer: usize,
+    pub instruction: i64,
+    pub relative_base: i64,
+    pub instruction_count: u64,
+    // Oldest first, not including the failing instruction.
+    pub recent_addresses: Vec<usize>,
+}
+
+// A `ProgramError` raised while running a machine, with the machine's state
+// at the time.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ExecutionError {
+    pub kind: ProgramError,
+    pub context: ErrorContext,
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let context = &self.context;
+        write!(
+            f,
+            "{} at address {} (instruction {}, relative base {}, after {} instructions",
+            self.kind,
+            context.instruction_pointer,
+            context.instruction,
+            context.relative_base,
+            context.instruction_count
+        )?;
+
+        if !context.recent_addresses.is_empty() {
+            let recent: Vec<String> = context
+                .recent_addresses
+                .iter()
+                .map(|address| address.to_string())
+                .collect();
+            write!(f, "; came from {}", recent.join(", "))?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl Error for ExecutionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.kind)
+    }
+}
+
+// For code which only cares what went wrong.
+impl From<ExecutionError> for ProgramError {
+    fn from(error: ExecutionError) -> Self {
+        error.kind
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct NonAsciiInput(pub char);
+
+impl fmt::Display for NonAsciiInput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Cannot send non-ASCII character {:?}", self.0)
+    }
+}
+
+impl Error for NonAsciiInput {}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AssemblyErrorKind {
+    UnknownMnemonic(String),
+    WrongOperandCount { expected: usize, found: usize },
+    InvalidOperand(String),
+    InvalidLabel(String),
+    DuplicateLabel(String),
+    UndefinedLabel(String),
+    OffsetOutOfRange(String),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AssemblyError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: AssemblyErrorKind,
+}
+
+impl fmt::Display for AssemblyErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssemblyErrorKind::UnknownMnemonic(name) => write!(f, "Unknown mnemonic {}", name),
+            AssemblyErrorKind::WrongOperandCount { expected, found } => write!(
+                f,
+                "Expected {} operand(s) but found {}",
+                expected, found
+            ),
+            AssemblyErrorKind::InvalidOperand(text) => write!(f, "Invalid operand {}", text),
+            AssemblyErrorKind::InvalidLabel(text) => write!(f, "Invalid label {}", text),
+            AssemblyErrorKind::DuplicateLabel(name) => write!(f, "Label {} is already defined", name),
+            AssemblyErrorKind::UndefinedLabel(name) => write!(f, "Undefined label {}", name),
+            AssemblyErrorKind::OffsetOutOfRange(name) => {
+                write!(f, "Offset from label {} is out of range", name)
+            }
+        }
+    }
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Line {}, column {}: {}", self.line, self.column, self.kind)
+    }
+}
+
+impl Error for AssemblyError {}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    NotASnapshot,
+    UnsupportedVersion(u32),
+    Truncated,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "I/O error: {}", e),
+            SnapshotError::NotASnapshot => write!(f, "Not an Intcode snapshot file"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported snapshot version {}", version)
+            }
+            SnapshotError::Truncated => write!(f, "Snapshot file is truncated"),
+        }
+    }
+}
+
+impl Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SnapshotError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::UnexpectedEof => SnapshotError::Truncated,
+            _ => SnapshotError::Io(error),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DataflowError {
+    Program { node: String, error: ExecutionError },
+    Deadlock(Vec<String>),
+    NoNodes,
+    NoOutput(String),
+}
+
+impl fmt::Display for DataflowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataflowError::Program { node, error } => write!(f, "Node {}: {}", node, error),
+            DataflowError::Deadlock(nodes) => write!(
+                f,
+                "Deadlock: {} waiting for input that will never arrive",
+                nodes.join(", ")
+            ),
+            DataflowError::NoNodes => write!(f, "The graph has no nodes"),
+            DataflowError::NoOutput(node) => write!(f, "Node {} halted without output", node),
+        }
+    }
+}
+
+impl Error for DataflowError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DataflowError::Program { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum NetworkError {
+    // Every node which failed during a round, with its address.
+    Programs(Vec<(usize, ExecutionError)>),
+    UnknownDestination(Packet),
+    Stalled,
+    AllHalted,
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetworkError::Programs(failures) => {
+                let failures: Vec<String> = failures
+                    .iter()
+                    .map(|(node, error)| format!("Node {}: {}", node, error))
+                    .collect();
+                write!(f, "{}", failures.join("; "))
+            }
+            NetworkError::UnknownDestination(packet) => {
+                write!(f, "No node at address {}", packet.destination)
+            }
+            NetworkError::Stalled => write!(f, "The network is idle and nothing will wake it"),
+            NetworkError::AllHalted => write!(f, "Every node has halted"),
+        }
+    }
+}
+
+impl Error for NetworkError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            NetworkError::Programs(failures) => failures.first().map(|(_, error)| error as _),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    // A path of "-" means standard input.
+    Io { path: String, error: io::Error },
+    Invalid(ParseIntError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io { path, error } if path == "-" => {
+                write!(f, "Cannot read program: {}", error)
+            }
+            LoadError::Io { path, error } => write!(f, "Cannot read {}: {}", path, error),
+            LoadError::Invalid(error) => write!(f, "Invalid program: {}", error),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io { error, .. } => Some(error),
+            LoadError::Invalid(error) => Some(error),
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use crate::errors::ProgramError;
 use crate::ProgramStore;
+use std::fmt;
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Argument {
@@ -19,9 +20,36 @@ impl Argument {
             other => Err(ProgramError::UnknownParameterMode(other)),
         }
     }
+
+    pub fn mode(&self) -> i64 {
+        match self {
+            Argument::Position(_) => 0,
+            Argument::Immediate(_) => 1,
+            Argument::Relative(_) => 2,
+        }
+    }
+
+    pub fn value(&self) -> i64 {
+        match *self {
+            Argument::Position(address) => address as i64,
+            Argument::Immediate(value) | Argument::Relative(value) => value,
+        }
+    }
+}
+
+impl fmt::Display for Argument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            // Raw words below zero still decode to positions.
+            Argument::Position(address) => write!(f, "[{}]", address as i64),
+            Argument::Immediate(value) => write!(f, "{}", value),
+            Argument::Relative(offset) if offset < 0 => write!(f, "@rb-{}", offset.unsigned_abs()),
+            Argument::Relative(offset) => write!(f, "@rb+{}", offset),
+        }
+    }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Instruction {
     Add(Argument, Argument, Argument),
     Multiply(Argument, Argument, Argument),
@@ -35,6 +63,17 @@ pub enum Instruction {
     Halt,
 }
 
+// Where control can go after an instruction, as far as can be told without
+// running it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Exit {
+    FallThrough(usize),
+    // A jump to an immediate target, which may be outside the program.
+    Jump(i64),
+    // A jump to a target read from memory.
+    Indirect,
+}
+
 impl Instruction {
     pub fn read(
         program: &ProgramStore,
@@ -106,6 +145,107 @@ impl Instruction {
             Instruction::JumpIfFalse(_, _) | Instruction::JumpIfTrue(_, _) => 3,
         }
     }
+
+    pub fn opcode(&self) -> i64 {
+        match self {
+            Instruction::Add(_, _, _) => 1,
+            Instruction::Multiply(_, _, _) => 2,
+            Instruction::Input(_) => 3,
+            Instruction::Output(_) => 4,
+            Instruction::JumpIfTrue(_, _) => 5,
+            Instruction::JumpIfFalse(_, _) => 6,
+            Instruction::LessThan(_, _, _) => 7,
+            Instruction::Equals(_, _, _) => 8,
+            Instruction::SetRelativeBase(_) => 9,
+            Instruction::Halt => 99,
+        }
+    }
+
+    // The inverse of `Instruction::read`.
+    pub fn encode(&self) -> Vec<i64> {
+        let arguments = self.arguments();
+        let modes: i64 = arguments
+            .iter()
+            .enumerate()
+            .map(|(index, argument)| argument.mode() * 10i64.pow(index as u32 + 2))
+            .sum();
+
+        let mut words = vec![modes + self.opcode()];
+        words.extend(arguments.iter().map(Argument::value));
+        words
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Add(_, _, _) => "ADD",
+            Instruction::Multiply(_, _, _) => "MUL",
+            Instruction::Input(_) => "IN",
+            Instruction::Output(_) => "OUT",
+            Instruction::JumpIfTrue(_, _) => "JT",
+            Instruction::JumpIfFalse(_, _) => "JF",
+            Instruction::LessThan(_, _, _) => "LT",
+            Instruction::Equals(_, _, _) => "EQ",
+            Instruction::SetRelativeBase(_) => "ARB",
+            Instruction::Halt => "HLT",
+        }
+    }
+
+    // The argument an instruction writes to, if it writes at all.
+    pub fn destination(&self) -> Option<Argument> {
+        match *self {
+            Instruction::Add(_, _, c)
+            | Instruction::Multiply(_, _, c)
+            | Instruction::LessThan(_, _, c)
+            | Instruction::Equals(_, _, c) => Some(c),
+            Instruction::Input(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn arguments(&self) -> Vec<Argument> {
+        match *self {
+            Instruction::Add(a, b, c)
+            | Instruction::Multiply(a, b, c)
+            | Instruction::LessThan(a, b, c)
+            | Instruction::Equals(a, b, c) => vec![a, b, c],
+            Instruction::JumpIfTrue(a, b) | Instruction::JumpIfFalse(a, b) => vec![a, b],
+            Instruction::Input(a) | Instruction::Output(a) | Instruction::SetRelativeBase(a) => {
+                vec![a]
+            }
+            Instruction::Halt => vec![],
+        }
+    }
+
+    pub fn exits(&self, address: usize) -> Vec<Exit> {
+        let next = Exit::FallThrough(address + self.arity());
+        let (condition, target, jump_if) = match *self {
+            Instruction::Halt => return vec![],
+            Instruction::JumpIfTrue(condition, target) => (condition, target, true),
+            Instruction::JumpIfFalse(condition, target) => (condition, target, false),
+            _ => return vec![next],
+        };
+
+        let jump = match target {
+            Argument::Immediate(target) => Exit::Jump(target),
+            _ => Exit::Indirect,
+        };
+        match condition {
+            Argument::Immediate(value) if (value != 0) == jump_if => vec![jump],
+            Argument::Immediate(_) => vec![next],
+            _ => vec![jump, next],
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.mnemonic())?;
+        for (index, argument) in self.arguments().iter().enumerate() {
+            let separator = if index == 0 { " " } else { ", " };
+            write!(f, "{}{}", separator, argument)?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -122,6 +262,91 @@ mod tests {
         );
     }
 
+    #[test]
+    fn encode_is_inverse_of_read() {
+        let arguments = [
+            Argument::Position(7),
+            Argument::Immediate(-5),
+            Argument::Relative(3),
+        ];
+        for &a in &arguments {
+            for &b in &arguments {
+                for &c in &arguments {
+                    let instructions = vec![
+                        Instruction::Add(a, b, c),
+                        Instruction::Multiply(a, b, c),
+                        Instruction::Input(a),
+                        Instruction::Output(b),
+                        Instruction::JumpIfTrue(a, b),
+                        Instruction::JumpIfFalse(b, c),
+                        Instruction::LessThan(a, b, c),
+                        Instruction::Equals(c, b, a),
+                        Instruction::SetRelativeBase(c),
+                        Instruction::Halt,
+                    ];
+                    for instruction in instructions {
+                        let program: ProgramStore = instruction.encode().into_iter().collect();
+                        assert_eq!(Instruction::read(&program, 0), Ok(instruction));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn encode_multiply() {
+        let instruction = Instruction::Multiply(
+            Argument::Position(4),
+            Argument::Immediate(3),
+            Argument::Position(4),
+        );
+        assert_eq!(instruction.encode(), vec![1002, 4, 3, 4]);
+    }
+
+    #[test]
+    fn display_instruction() {
+        let instruction = Instruction::Add(
+            Argument::Position(4),
+            Argument::Immediate(-3),
+            Argument::Relative(-1),
+        );
+        assert_eq!(instruction.to_string(), "ADD [4], -3, @rb-1");
+        assert_eq!(Instruction::Halt.to_string(), "HLT");
+    }
+
+    #[test]
+    fn display_extreme_arguments() {
+        assert_eq!(Argument::Position(-1i64 as usize).to_string(), "[-1]");
+        assert_eq!(
+            Argument::Relative(i64::MIN).to_string(),
+            "@rb-9223372036854775808"
+        );
+    }
+
+    #[test]
+    fn exits_follow_constant_conditions() {
+        let jump = Argument::Immediate(10);
+        let condition = Argument::Position(20);
+        let (yes, no) = (Argument::Immediate(1), Argument::Immediate(0));
+        assert_eq!(
+            Instruction::JumpIfTrue(condition, jump).exits(0),
+            vec![Exit::Jump(10), Exit::FallThrough(3)]
+        );
+        assert_eq!(
+            Instruction::JumpIfTrue(yes, jump).exits(0),
+            vec![Exit::Jump(10)]
+        );
+        assert_eq!(
+            Instruction::JumpIfFalse(yes, condition).exits(0),
+            vec![Exit::FallThrough(3)]
+        );
+        assert_eq!(
+            Instruction::JumpIfFalse(no, condition).exits(0),
+            vec![Exit::Indirect]
+        );
+        assert_eq!(Instruction::Halt.exits(4), vec![]);
+    }
+
     #[test]
     fn argument_new_first_parameter() {
         assert_eq!(Argument::new(2000, 0, 1), Ok(Argument::Position(1)));
@@ -0,0 +1,170 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::{Receiver, Sender, SyncSender};
+
+// Asked for a value each time the program executes an input instruction.
+// Returning `None` leaves the machine waiting for input, and the same
+// instruction asks again when it is next run.
+pub trait InputSource {
+    fn next_input(&mut self) -> Option<i64>;
+}
+
+// Given each value as soon as the program outputs it.
+pub trait OutputSink {
+    fn write_output(&mut self, value: i64);
+}
+
+// The machine's own input queue.
+impl InputSource for VecDeque<i64> {
+    fn next_input(&mut self) -> Option<i64> {
+        self.pop_front()
+    }
+}
+
+impl OutputSink for Vec<i64> {
+    fn write_output(&mut self, value: i64) {
+        self.push(value);
+    }
+}
+
+impl OutputSink for VecDeque<i64> {
+    fn write_output(&mut self, value: i64) {
+        self.push_back(value);
+    }
+}
+
+pub struct IterSource<I>(I);
+
+impl<I: Iterator<Item = i64>> InputSource for IterSource<I> {
+    fn next_input(&mut self) -> Option<i64> {
+        self.0.next()
+    }
+}
+
+pub fn from_iter<I>(values: I) -> IterSource<I::IntoIter>
+where
+    I: IntoIterator<Item = i64>,
+{
+    IterSource(values.into_iter())
+}
+
+pub struct FnSource<F>(F);
+
+impl<F: FnMut() -> Option<i64>> InputSource for FnSource<F> {
+    fn next_input(&mut self) -> Option<i64> {
+        (self.0)()
+    }
+}
+
+pub fn from_fn<F: FnMut() -> Option<i64>>(f: F) -> FnSource<F> {
+    FnSource(f)
+}
+
+pub struct FnSink<F>(F);
+
+impl<F: FnMut(i64)> OutputSink for FnSink<F> {
+    fn write_output(&mut self, value: i64) {
+        (self.0)(value)
+    }
+}
+
+pub fn to_fn<F: FnMut(i64)>(f: F) -> FnSink<F> {
+    FnSink(f)
+}
+
+// Blocks until a value arrives. Once every sender has gone the machine is
+// left waiting for input.
+impl InputSource for Receiver<i64> {
+    fn next_input(&mut self) -> Option<i64> {
+        self.recv().ok()
+    }
+}
+
+// Outputs sent after the receiver has gone are dropped.
+impl OutputSink for Sender<i64> {
+    fn write_output(&mut self, value: i64) {
+        let _ = self.send(value);
+    }
+}
+
+impl OutputSink for SyncSender<i64> {
+    fn write_output(&mut self, value: i64) {
+        let _ = self.send(value);
+    }
+}
+
+// Discards every output.
+pub struct NullSink;
+
+impl OutputSink for NullSink {
+    fn write_output(&mut self, _value: i64) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IntcodeMachine, StopReason};
+    use std::cell::RefCell;
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    // Echoes each input doubled, forever.
+    fn doubler() -> Vec<i64> {
+        vec![3, 11, 1002, 11, 2, 11, 4, 11, 1105, 1, 0, 0]
+    }
+
+    #[test]
+    fn iterator_input_and_vec_output() {
+        let mut machine = IntcodeMachine::new(doubler());
+        let mut outputs = Vec::new();
+        let result = machine.run_with(&mut from_iter(vec![1, 2, 3]), &mut outputs);
+        assert_eq!(result, Ok(StopReason::PendingInput));
+        assert_eq!(outputs, vec![2, 4, 6]);
+    }
+
+    #[test]
+    fn closures_react_to_each_output() {
+        // Feed each output straight back in until it passes 100.
+        let last = RefCell::new(1);
+        let mut machine = IntcodeMachine::new(doubler());
+        let result = machine.run_with(
+            &mut from_fn(|| Some(*last.borrow()).filter(|&v| v < 100)),
+            &mut to_fn(|v| *last.borrow_mut() = v),
+        );
+        assert_eq!(result, Ok(StopReason::PendingInput));
+        assert_eq!(*last.borrow(), 128);
+    }
+
+    #[test]
+    fn channels_connect_machines_across_threads() {
+        let (input_tx, mut input_rx) = channel();
+        let (mut output_tx, output_rx) = channel();
+
+        let worker = thread::spawn(move || {
+            let mut machine = IntcodeMachine::new(doubler());
+            machine.run_with(&mut input_rx, &mut output_tx)
+        });
+
+        for n in 1..=3 {
+            input_tx.send(n).unwrap();
+            assert_eq!(output_rx.recv(), Ok(n * 2));
+        }
+        drop(input_tx);
+
+        assert_eq!(worker.join().unwrap(), Ok(StopReason::PendingInput));
+    }
+
+    #[test]
+    fn queued_inputs_are_untouched_by_external_sources() {
+        let mut machine = IntcodeMachine::new(doubler());
+        machine.add_input(5);
+        let mut outputs = Vec::new();
+        machine
+            .run_with(&mut from_iter(vec![1]), &mut outputs)
+            .unwrap();
+        assert_eq!(outputs, vec![2]);
+        assert_eq!(
+            machine.pending_inputs().cloned().collect::<Vec<i64>>(),
+            vec![5]
+        );
+    }
+}
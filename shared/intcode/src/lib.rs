@@ -1,58 +1,148 @@
+pub mod arithmetic;
+pub mod ascii;
+pub mod assembler;
+pub mod async_io;
+pub mod breakpoints;
+pub mod cache;
+pub mod control_flow;
+pub mod dataflow;
+pub mod disassembler;
 pub mod errors;
 pub mod instructions;
+pub mod io;
+pub mod limits;
+pub mod loops;
+pub mod network;
+pub mod outputs;
+pub mod snapshot;
+pub mod store;
+pub mod trace;
+pub mod verifier;
 use std::str::FromStr;
-pub use crate::errors::ProgramError;
+pub use crate::arithmetic::Arithmetic;
+pub use crate::ascii::{AsciiMachine, AsciiOutput};
+pub use crate::async_io::{AsyncInputSource, AsyncOutputs};
+pub use crate::dataflow::{Dataflow, NodeId};
+pub use crate::errors::{
+    AssemblyError, DataflowError, ErrorContext, ExecutionError, LoadError, NetworkError,
+    NonAsciiInput, ProgramError, SnapshotError,
+};
+pub use crate::breakpoints::Breakpoint;
+pub use crate::cache::ExecutionMode;
+use crate::cache::DecodeCache;
 use crate::instructions::Argument;
-use core::ops::{Index, IndexMut};
+use crate::loops::LoopDetector;
+pub use crate::io::{InputSource, OutputSink};
+pub use crate::limits::Limits;
+pub use crate::network::{Nat, Network, NetworkEvent, Packet};
+pub use crate::outputs::{OutputEvent, OutputGroups, Outputs};
+pub use crate::snapshot::Snapshot;
+pub use crate::store::ProgramStore;
+use crate::trace::{TraceEvent, TraceSink};
 use instructions::Instruction;
-use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::VecDeque;
+use std::fs;
+use std::io::Read;
 use std::iter::FromIterator;
+use std::mem;
+use std::time::Instant;
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum ProgramState {
     Completed(Vec<i64>),
     PendingInput(Vec<i64>),
+    Breakpoint(Vec<i64>, Breakpoint),
 }
 
-#[derive(Debug, Default)]
-pub struct ProgramStore(BTreeMap<usize, i64>);
-
-impl ProgramStore {
-    pub fn new() -> ProgramStore {
-        ProgramStore(BTreeMap::new())
-    }
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StopReason {
+    Halted,
+    PendingInput,
+    Breakpoint(Breakpoint),
 }
 
-impl Index<usize> for ProgramStore {
-    type Output = i64;
-    fn index(&self, index: usize) -> &Self::Output {
-        self.0.get(&index).unwrap_or(&0)
-    }
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StepStatus {
+    Running,
+    BlockedOnInput,
+    Halted,
 }
 
-impl IndexMut<usize> for ProgramStore {
-    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        self.0.entry(index).or_insert(0)
-    }
+// A lenient machine accepts anything it can make sense of: an immediate
+// destination is treated as a position, and negative addresses and jump
+// targets wrap around. A strict one stops with an error instead.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Strictness {
+    #[default]
+    Lenient,
+    Strict,
 }
 
-impl FromIterator<i64> for ProgramStore {
-    fn from_iter<I>(iter: I) -> Self
-    where
-        I: IntoIterator<Item = i64>,
-    {
-        let map: BTreeMap<usize, i64> = iter.into_iter().enumerate().collect();
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MemoryWrite {
+    pub address: usize,
+    pub value: i64,
+}
 
-        ProgramStore(map)
-    }
+// The outcome of executing a single instruction. When the status is
+// `BlockedOnInput` or `Halted` the instruction pointer has not moved.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Step {
+    pub address: usize,
+    pub instruction: Instruction,
+    pub output: Option<i64>,
+    pub write: Option<MemoryWrite>,
+    pub status: StepStatus,
 }
 
+// How many of the most recently executed addresses an `ExecutionError`
+// reports.
+const RECENT_ADDRESSES: usize = 8;
+
+// How many instructions run between looking at the clock, when there is a
+// deadline.
+const DEADLINE_INTERVAL: u64 = 1024;
+
 pub struct IntcodeMachine {
     program: ProgramStore,
     instruction_ptr: usize,
     relative_base: i64,
     input_queue: VecDeque<i64>,
+    breakpoints: BTreeSet<Breakpoint>,
+    resume_address: Option<usize>,
+    tracer: Option<Box<dyn TraceSink + Send>>,
+    decode_cache: Option<DecodeCache>,
+    partial_outputs: Vec<i64>,
+    instruction_count: u64,
+    recent_addresses: VecDeque<usize>,
+    strictness: Strictness,
+    arithmetic: Arithmetic,
+    limits: Limits,
+    loop_detector: Option<LoopDetector>,
+}
+
+// Clones share nothing with the original. A trace sink is not cloned.
+impl Clone for IntcodeMachine {
+    fn clone(&self) -> Self {
+        IntcodeMachine {
+            program: self.program.clone(),
+            instruction_ptr: self.instruction_ptr,
+            relative_base: self.relative_base,
+            input_queue: self.input_queue.clone(),
+            breakpoints: self.breakpoints.clone(),
+            resume_address: self.resume_address,
+            tracer: None,
+            decode_cache: self.decode_cache.clone(),
+            partial_outputs: self.partial_outputs.clone(),
+            instruction_count: self.instruction_count,
+            recent_addresses: self.recent_addresses.clone(),
+            strictness: self.strictness,
+            arithmetic: self.arithmetic,
+            limits: self.limits,
+            loop_detector: self.loop_detector.clone(),
+        }
+    }
 }
 
 impl IntcodeMachine {
@@ -65,6 +155,17 @@ impl IntcodeMachine {
             instruction_ptr: 0,
             relative_base: 0,
             input_queue: VecDeque::new(),
+            breakpoints: BTreeSet::new(),
+            resume_address: None,
+            tracer: None,
+            decode_cache: None,
+            partial_outputs: Vec::new(),
+            instruction_count: 0,
+            recent_addresses: VecDeque::with_capacity(RECENT_ADDRESSES),
+            strictness: Strictness::Lenient,
+            arithmetic: Arithmetic::Wrapping,
+            limits: Limits::default(),
+            loop_detector: None,
         }
     }
 
@@ -88,102 +189,696 @@ impl IntcodeMachine {
         }
     }
 
+    pub fn instruction_pointer(&self) -> usize {
+        self.instruction_ptr
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
+    }
+
+    // The number of instructions executed so far, not counting halts or
+    // inputs which found no input waiting.
+    pub fn instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+
+    pub fn memory(&self) -> &ProgramStore {
+        &self.program
+    }
+
+    // Changes a memory cell from outside the program, for instance to patch
+    // it before running.
+    pub fn poke(&mut self, address: usize, value: i64) {
+        if let Some(detector) = self.loop_detector.as_mut() {
+            detector.record_write(address, self.program[address], value);
+        }
+        self.program[address] = value;
+        if let Some(cache) = self.decode_cache.as_mut() {
+            cache.invalidate(address);
+        }
+    }
+
+    pub fn pending_inputs(&self) -> impl Iterator<Item = &i64> {
+        self.input_queue.iter()
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+        self.breakpoints.insert(breakpoint)
+    }
+
+    pub fn remove_breakpoint(&mut self, breakpoint: &Breakpoint) -> bool {
+        self.breakpoints.remove(breakpoint)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.breakpoints.iter()
+    }
+
+    // Lets the next call to `run` execute the current instruction even if a
+    // breakpoint is set on it, as it does after stopping there.
+    pub fn step_over_breakpoint(&mut self) {
+        self.resume_address = Some(self.instruction_ptr);
+    }
+
+    pub fn set_trace_sink<S>(&mut self, sink: S)
+    where
+        S: TraceSink + Send + 'static,
+    {
+        self.tracer = Some(Box::new(sink));
+    }
+
+    pub fn take_trace_sink(&mut self) -> Option<Box<dyn TraceSink + Send>> {
+        self.tracer.take()
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memory: self.program.clone(),
+            instruction_pointer: self.instruction_ptr,
+            relative_base: self.relative_base,
+            instruction_count: self.instruction_count,
+            input_queue: self.input_queue.clone(),
+        }
+    }
+
+    // Breakpoints, the trace sink and the execution mode are kept.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.program = snapshot.memory.clone();
+        self.instruction_ptr = snapshot.instruction_pointer;
+        self.relative_base = snapshot.relative_base;
+        self.instruction_count = snapshot.instruction_count;
+        self.input_queue = snapshot.input_queue.clone();
+        self.resume_address = None;
+        self.partial_outputs.clear();
+        self.recent_addresses.clear();
+        if let Some(cache) = self.decode_cache.as_mut() {
+            cache.clear();
+        }
+        if let Some(detector) = self.loop_detector.as_mut() {
+            detector.reset(&self.program);
+        }
+    }
+
+    pub fn set_execution_mode(&mut self, mode: ExecutionMode) {
+        self.decode_cache = match mode {
+            ExecutionMode::Interpreted => None,
+            ExecutionMode::Cached => Some(DecodeCache::new()),
+        };
+    }
+
+    pub fn execution_mode(&self) -> ExecutionMode {
+        match self.decode_cache {
+            Some(_) => ExecutionMode::Cached,
+            None => ExecutionMode::Interpreted,
+        }
+    }
+
+    pub fn set_strictness(&mut self, strictness: Strictness) {
+        self.strictness = strictness;
+    }
+
+    pub fn strictness(&self) -> Strictness {
+        self.strictness
+    }
+
+    pub fn set_arithmetic(&mut self, arithmetic: Arithmetic) {
+        self.arithmetic = arithmetic;
+    }
+
+    pub fn arithmetic(&self) -> Arithmetic {
+        self.arithmetic
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    // Stops the machine with `ProgramError::InfiniteLoop` if it comes back
+    // to the same state without any input or output in between.
+    pub fn set_loop_detection(&mut self, enabled: bool) {
+        self.loop_detector = if enabled {
+            Some(LoopDetector::new(&self.program))
+        } else {
+            None
+        };
+    }
+
+    pub fn loop_detection(&self) -> bool {
+        self.loop_detector.is_some()
+    }
+
     fn get_value(&self, argument: &Argument) -> i64 {
         match *argument {
             Argument::Immediate(value) => value,
             Argument::Position(index) => self.program[index],
-            Argument::Relative(index) => self.program[(self.relative_base as i64 + index) as usize],
+            Argument::Relative(index) => {
+                self.program[self.relative_base.wrapping_add(index) as usize]
+            }
         }
     }
 
-    pub fn run(&mut self) -> Result<ProgramState, ProgramError> {
-        let mut outputs = Vec::new();
-        let mut jumped: bool;
-        loop {
-            jumped = false;
-            let instruction = Instruction::read(&self.program, self.instruction_ptr)?;
-            match instruction {
-                Instruction::Add(a, b, destination) => {
-                    let index = match destination {
-                        Argument::Immediate(n) => n as usize,
-                        Argument::Position(n) => n as usize,
-                        Argument::Relative(n) => (n + self.relative_base) as usize,
-                    };
-                    self.program[index] = self.get_value(&a) + self.get_value(&b);
+    fn get_address(&self, argument: &Argument) -> usize {
+        match *argument {
+            Argument::Immediate(n) => n as usize,
+            Argument::Position(n) => n,
+            Argument::Relative(n) => n.wrapping_add(self.relative_base) as usize,
+        }
+    }
+
+    fn write(&mut self, destination: &Argument, value: i64) -> MemoryWrite {
+        let address = self.get_address(destination);
+        if let Some(detector) = self.loop_detector.as_mut() {
+            detector.record_write(address, self.program[address], value);
+        }
+        self.program[address] = value;
+        if let Some(cache) = self.decode_cache.as_mut() {
+            cache.invalidate(address);
+        }
+        MemoryWrite { address, value }
+    }
+
+    fn fault(&self, kind: ProgramError) -> ExecutionError {
+        ExecutionError {
+            kind,
+            context: ErrorContext {
+                instruction_pointer: self.instruction_ptr,
+                instruction: self.program[self.instruction_ptr],
+                relative_base: self.relative_base,
+                instruction_count: self.instruction_count,
+                recent_addresses: self.recent_addresses.iter().cloned().collect(),
+            },
+        }
+    }
+
+    pub fn step(&mut self) -> Result<Step, ExecutionError> {
+        let mut queue = mem::take(&mut self.input_queue);
+        let result = self.step_with(&mut queue);
+        self.input_queue = queue;
+        result
+    }
+
+    pub fn step_with(&mut self, input: &mut dyn InputSource) -> Result<Step, ExecutionError> {
+        let address = self.instruction_ptr;
+        self.check_budget().map_err(|kind| self.fault(kind))?;
+        let decoded = match self.decode_cache.as_mut() {
+            Some(cache) => cache.decode(&self.program, address),
+            None => Instruction::read(&self.program, address),
+        };
+        let instruction = decoded.map_err(|kind| self.fault(kind))?;
+        if self.strictness == Strictness::Strict {
+            self.check_strict(address, &instruction)
+                .map_err(|kind| self.fault(kind))?;
+        }
+        self.check_accesses(&instruction)
+            .map_err(|kind| self.fault(kind))?;
+
+        if self.tracer.is_none() {
+            return self
+                .execute(address, instruction, input)
+                .map_err(|kind| self.fault(kind));
+        }
+
+        let operands = self.resolve_operands(&instruction);
+        let previous_base = self.relative_base;
+        let step = self
+            .execute(address, instruction, input)
+            .map_err(|kind| self.fault(kind))?;
+
+        if step.status != StepStatus::BlockedOnInput {
+            let event = TraceEvent {
+                address,
+                instruction: step.instruction,
+                operands,
+                write: step.write,
+                output: step.output,
+                relative_base: Some(self.relative_base).filter(|&base| base != previous_base),
+            };
+            if let Some(tracer) = self.tracer.as_mut() {
+                tracer.record(&event);
+            }
+        }
+
+        Ok(step)
+    }
+
+    // The limits which can be checked before decoding the next instruction.
+    fn check_budget(&self) -> Result<(), ProgramError> {
+        let limits = &self.limits;
+        if let Some(max) = limits.max_instructions {
+            if self.instruction_count >= max {
+                return Err(ProgramError::InstructionLimit(max));
+            }
+        }
+        if let Some(deadline) = limits.deadline {
+            let due = self.instruction_count % DEADLINE_INTERVAL == 0;
+            if due && Instant::now() >= deadline {
+                return Err(ProgramError::DeadlineExceeded);
+            }
+        }
+        if let Some(max) = limits.max_address {
+            if self.instruction_ptr > max {
+                return Err(ProgramError::AddressLimit(self.instruction_ptr));
+            }
+        }
+        Ok(())
+    }
+
+    // Checks the addresses an instruction will read and write against the
+    // limits, before it runs.
+    fn check_accesses(&self, instruction: &Instruction) -> Result<(), ProgramError> {
+        let destination = instruction.destination();
+
+        if let Some(max) = self.limits.max_address {
+            // The instruction's own operands are fetched as well.
+            let last_word = self
+                .instruction_ptr
+                .saturating_add(instruction.arity() - 1);
+            if last_word > max {
+                return Err(ProgramError::AddressLimit(last_word));
+            }
+
+            for argument in instruction.arguments() {
+                if let Argument::Immediate(_) = argument {
+                    if Some(argument) != destination {
+                        continue;
+                    }
                 }
-                Instruction::Multiply(a, b, destination) => {
-                    let index = match destination {
-                        Argument::Immediate(n) => n as usize,
-                        Argument::Position(n) => n as usize,
-                        Argument::Relative(n) => (n + self.relative_base) as usize,
-                    };
-                    self.program[index] = self.get_value(&a) * self.get_value(&b);
+                let address = self.get_address(&argument);
+                if address > max {
+                    return Err(ProgramError::AddressLimit(address));
                 }
-                Instruction::Input(destination) => match self.input_queue.pop_front() {
-                    Some(input) => {
-                        let index = match destination {
-                            Argument::Immediate(n) => n as usize,
-                            Argument::Position(n) => n as usize,
-                            Argument::Relative(n) => (n + self.relative_base) as usize,
-                        };
-                        self.program[index] = input;
-                    }
-                    None => {
-                        return Ok(ProgramState::PendingInput(outputs));
-                    }
-                },
-                Instruction::Output(value) => {
-                    let v = self.get_value(&value);
-                    outputs.push(v);
+            }
+        }
+
+        if let (Some(max), Some(destination)) = (self.limits.max_cells, destination) {
+            let address = self.get_address(&destination);
+            if self.program.cell_count_after_write(address) > max {
+                return Err(ProgramError::MemoryLimit(max));
+            }
+        }
+        Ok(())
+    }
+
+    // Rejects anything a lenient machine would quietly reinterpret, before
+    // the instruction has changed anything.
+    fn check_strict(&self, address: usize, instruction: &Instruction) -> Result<(), ProgramError> {
+        let word = self.program[address];
+        if word / 10i64.pow(instruction.arity() as u32 + 1) != 0 {
+            return Err(ProgramError::UnusedParameterMode(word));
+        }
+
+        if let Some(Argument::Immediate(value)) = instruction.destination() {
+            return Err(ProgramError::ImmediateDestination(value));
+        }
+
+        for argument in instruction.arguments() {
+            let effective = match argument {
+                Argument::Immediate(_) => continue,
+                Argument::Position(address) => address as i64,
+                Argument::Relative(offset) => offset.wrapping_add(self.relative_base),
+            };
+            if effective < 0 {
+                return Err(ProgramError::NegativeAddress(effective));
+            }
+        }
+
+        let target = match *instruction {
+            Instruction::JumpIfTrue(value, target) if self.get_value(&value) != 0 => target,
+            Instruction::JumpIfFalse(value, target) if self.get_value(&value) == 0 => target,
+            _ => return Ok(()),
+        };
+        match self.get_value(&target) {
+            target if target < 0 => Err(ProgramError::NegativeJump(target)),
+            _ => Ok(()),
+        }
+    }
+
+    // Each argument resolved to the value it reads, or for a destination the
+    // address it writes to.
+    fn resolve_operands(&self, instruction: &Instruction) -> Vec<i64> {
+        let arguments = instruction.arguments();
+        let destination = match instruction {
+            Instruction::Add(_, _, _)
+            | Instruction::Multiply(_, _, _)
+            | Instruction::LessThan(_, _, _)
+            | Instruction::Equals(_, _, _)
+            | Instruction::Input(_) => Some(arguments.len() - 1),
+            _ => None,
+        };
+
+        arguments
+            .iter()
+            .enumerate()
+            .map(|(index, argument)| {
+                if Some(index) == destination {
+                    self.get_address(argument) as i64
+                } else {
+                    self.get_value(argument)
                 }
-                Instruction::JumpIfFalse(value, destination) => {
-                    if self.get_value(&value) == 0 {
-                        self.instruction_ptr = self.get_value(&destination) as usize;
-                        jumped = true;
-                    }
+            })
+            .collect()
+    }
+
+    fn execute(
+        &mut self,
+        address: usize,
+        instruction: Instruction,
+        input: &mut dyn InputSource,
+    ) -> Result<Step, ProgramError> {
+        let mut output = None;
+        let mut write = None;
+        let mut jumped = false;
+
+        match instruction {
+            Instruction::Add(a, b, destination) => {
+                let value = self
+                    .arithmetic
+                    .add(self.get_value(&a), self.get_value(&b))?;
+                write = Some(self.write(&destination, value));
+            }
+            Instruction::Multiply(a, b, destination) => {
+                let value = self
+                    .arithmetic
+                    .multiply(self.get_value(&a), self.get_value(&b))?;
+                write = Some(self.write(&destination, value));
+            }
+            Instruction::Input(destination) => match input.next_input() {
+                Some(input) => {
+                    write = Some(self.write(&destination, input));
                 }
-                Instruction::JumpIfTrue(value, destination) => {
-                    if self.get_value(&value) != 0 {
-                        self.instruction_ptr = self.get_value(&destination) as usize;
-                        jumped = true;
-                    }
+                None => {
+                    return Ok(Step {
+                        address,
+                        instruction,
+                        output,
+                        write,
+                        status: StepStatus::BlockedOnInput,
+                    });
                 }
-                Instruction::LessThan(a, b, destination) => {
-                    let index = match destination {
-                        Argument::Immediate(n) => n as usize,
-                        Argument::Position(n) => n as usize,
-                        Argument::Relative(n) => (n + self.relative_base) as usize,
-                    };
-                    self.program[index] = if self.get_value(&a) < self.get_value(&b) {
-                        1
-                    } else {
-                        0
-                    };
+            },
+            Instruction::Output(value) => {
+                output = Some(self.get_value(&value));
+            }
+            Instruction::JumpIfFalse(value, destination) => {
+                if self.get_value(&value) == 0 {
+                    self.instruction_ptr = self.get_value(&destination) as usize;
+                    jumped = true;
+                }
+            }
+            Instruction::JumpIfTrue(value, destination) => {
+                if self.get_value(&value) != 0 {
+                    self.instruction_ptr = self.get_value(&destination) as usize;
+                    jumped = true;
                 }
-                Instruction::Equals(a, b, destination) => {
-                    let index = match destination {
-                        Argument::Immediate(n) => n as usize,
-                        Argument::Position(n) => n as usize,
-                        Argument::Relative(n) => (n + self.relative_base) as usize,
-                    };
-                    self.program[index] = if self.get_value(&a) == self.get_value(&b) {
-                        1
-                    } else {
-                        0
-                    };
+            }
+            Instruction::LessThan(a, b, destination) => {
+                let value = if self.get_value(&a) < self.get_value(&b) {
+                    1
+                } else {
+                    0
+                };
+                write = Some(self.write(&destination, value));
+            }
+            Instruction::Equals(a, b, destination) => {
+                let value = if self.get_value(&a) == self.get_value(&b) {
+                    1
+                } else {
+                    0
+                };
+                write = Some(self.write(&destination, value));
+            }
+            Instruction::SetRelativeBase(a) => {
+                self.relative_base = self
+                    .arithmetic
+                    .add(self.relative_base, self.get_value(&a))?;
+            }
+            Instruction::Halt => {
+                return Ok(Step {
+                    address,
+                    instruction,
+                    output,
+                    write,
+                    status: StepStatus::Halted,
+                });
+            }
+        }
+
+        if !jumped {
+            self.instruction_ptr += instruction.arity();
+        }
+        self.resume_address = None;
+
+        self.instruction_count += 1;
+        if self.recent_addresses.len() == RECENT_ADDRESSES {
+            self.recent_addresses.pop_front();
+        }
+        self.recent_addresses.push_back(address);
+
+        if let Some(detector) = self.loop_detector.as_mut() {
+            if output.is_some() || instruction.opcode() == 3 {
+                detector.forget_states();
+            } else if jumped {
+                detector.visit(
+                    &self.program,
+                    self.instruction_ptr,
+                    self.relative_base,
+                    self.instruction_count,
+                )?;
+            }
+        }
+
+        Ok(Step {
+            address,
+            instruction,
+            output,
+            write,
+            status: StepStatus::Running,
+        })
+    }
+
+    // An address or opcode breakpoint which has just stopped the machine is
+    // ignored once, so that the next call to `run` makes progress.
+    fn breakpoint_before_step(&self) -> Option<Breakpoint> {
+        if self.resume_address == Some(self.instruction_ptr) {
+            return None;
+        }
+
+        let address = Breakpoint::Address(self.instruction_ptr);
+        let opcode = Breakpoint::Opcode(self.program[self.instruction_ptr] % 100);
+        if self.breakpoints.contains(&address) {
+            Some(address)
+        } else if self.breakpoints.contains(&opcode) {
+            Some(opcode)
+        } else {
+            None
+        }
+    }
+
+    fn source_addresses(&self, instruction: &Instruction) -> Vec<usize> {
+        let sources = match *instruction {
+            Instruction::Add(a, b, _)
+            | Instruction::Multiply(a, b, _)
+            | Instruction::LessThan(a, b, _)
+            | Instruction::Equals(a, b, _)
+            | Instruction::JumpIfTrue(a, b)
+            | Instruction::JumpIfFalse(a, b) => vec![a, b],
+            Instruction::Output(a) | Instruction::SetRelativeBase(a) => vec![a],
+            Instruction::Input(_) | Instruction::Halt => vec![],
+        };
+
+        sources
+            .iter()
+            .filter(|argument| !matches!(argument, Argument::Immediate(_)))
+            .map(|argument| self.get_address(argument))
+            .collect()
+    }
+
+    fn watchpoint_hit(&self, reads: &[usize], step: &Step) -> Option<Breakpoint> {
+        let write = step
+            .write
+            .map(|write| Breakpoint::Write(write.address))
+            .filter(|watch| self.breakpoints.contains(watch));
+
+        write.or_else(|| {
+            reads
+                .iter()
+                .map(|&address| Breakpoint::Read(address))
+                .find(|watch| self.breakpoints.contains(watch))
+        })
+    }
+
+    // Runs until the program halts, a breakpoint is hit, or the input source
+    // has nothing to give. Each output goes to the sink as soon as it is
+    // produced.
+    pub fn run_with(
+        &mut self,
+        input: &mut dyn InputSource,
+        output: &mut dyn OutputSink,
+    ) -> Result<StopReason, ExecutionError> {
+        match self.run_loop(input, output, None, None)? {
+            Some(reason) => Ok(reason),
+            None => unreachable!("No output limit was set"),
+        }
+    }
+
+    // As `run_with`, but also stops (returning `None`) once `max_outputs`
+    // values have been output. `buffered` is how many outputs the sink
+    // already holds, if it is one of the machine's own buffers.
+    fn run_loop(
+        &mut self,
+        input: &mut dyn InputSource,
+        output: &mut dyn OutputSink,
+        max_outputs: Option<usize>,
+        buffered: Option<usize>,
+    ) -> Result<Option<StopReason>, ExecutionError> {
+        let mut output_count = 0;
+        loop {
+            if max_outputs.is_some_and(|max| output_count >= max) {
+                return Ok(None);
+            }
+
+            if let (Some(held), Some(max)) = (buffered, self.limits.max_buffered_outputs) {
+                let opcode = self.program[self.instruction_ptr] % 100;
+                if opcode == 4 && held + output_count >= max {
+                    return Err(self.fault(ProgramError::OutputLimit(max)));
                 }
-                Instruction::SetRelativeBase(a) => {
-                    self.relative_base += self.get_value(&a);
+            }
+
+            let mut reads = Vec::new();
+            if !self.breakpoints.is_empty() {
+                if let Some(breakpoint) = self.breakpoint_before_step() {
+                    self.resume_address = Some(self.instruction_ptr);
+                    return Ok(Some(StopReason::Breakpoint(breakpoint)));
                 }
-                Instruction::Halt => {
-                    break;
+                if let Ok(instruction) = Instruction::read(&self.program, self.instruction_ptr) {
+                    reads = self.source_addresses(&instruction);
                 }
             }
-            if !jumped {
-                self.instruction_ptr += instruction.arity();
+
+            let step = self.step_with(input)?;
+            if let Some(value) = step.output {
+                output.write_output(value);
+                output_count += 1;
+            }
+            match step.status {
+                StepStatus::Running => {}
+                StepStatus::BlockedOnInput => return Ok(Some(StopReason::PendingInput)),
+                StepStatus::Halted => return Ok(Some(StopReason::Halted)),
+            }
+
+            if !self.breakpoints.is_empty() {
+                if let Some(watchpoint) = self.watchpoint_hit(&reads, &step) {
+                    return Ok(Some(StopReason::Breakpoint(watchpoint)));
+                }
             }
         }
-        Ok(ProgramState::Completed(outputs))
+    }
+
+    pub fn run_until_output(&mut self) -> Result<OutputEvent<i64>, ExecutionError> {
+        Ok(match self.run_until_outputs(1)? {
+            OutputEvent::Output(values) => OutputEvent::Output(values[0]),
+            OutputEvent::NeedsInput => OutputEvent::NeedsInput,
+            OutputEvent::Halted => OutputEvent::Halted,
+            OutputEvent::Breakpoint(breakpoint) => OutputEvent::Breakpoint(breakpoint),
+        })
+    }
+
+    // Runs until `count` values have been output. Values output before the
+    // machine stops for input or at a breakpoint are kept towards the next
+    // group. If the program halts part way through a group, that group is
+    // returned short, like the last of `slice::chunks`.
+    pub fn run_until_outputs(
+        &mut self,
+        count: usize,
+    ) -> Result<OutputEvent<Vec<i64>>, ExecutionError> {
+        assert!(count > 0, "Cannot run until zero outputs");
+        let mut group = mem::take(&mut self.partial_outputs);
+        if group.len() >= count {
+            self.partial_outputs = group.split_off(count);
+            return Ok(OutputEvent::Output(group));
+        }
+
+        let mut queue = mem::take(&mut self.input_queue);
+        let wanted = count.saturating_sub(group.len());
+        let held = group.len();
+        let result = self.run_loop(&mut queue, &mut group, Some(wanted), Some(held));
+        self.input_queue = queue;
+
+        let event = match result {
+            Ok(None) => return Ok(OutputEvent::Output(group)),
+            Ok(Some(StopReason::Halted)) if !group.is_empty() => {
+                return Ok(OutputEvent::Output(group));
+            }
+            Ok(Some(StopReason::Halted)) => OutputEvent::Halted,
+            Ok(Some(StopReason::PendingInput)) => OutputEvent::NeedsInput,
+            Ok(Some(StopReason::Breakpoint(breakpoint))) => OutputEvent::Breakpoint(breakpoint),
+            Err(e) => {
+                self.partial_outputs = group;
+                return Err(e);
+            }
+        };
+
+        self.partial_outputs = group;
+        Ok(event)
+    }
+
+    pub fn outputs(&mut self) -> Outputs<'_> {
+        Outputs::new(self)
+    }
+
+    pub fn output_groups(&mut self, size: usize) -> OutputGroups<'_> {
+        OutputGroups::new(self, size)
+    }
+
+    pub fn async_outputs<'a, I>(&'a mut self, input: &'a mut I) -> AsyncOutputs<'a, I>
+    where
+        I: AsyncInputSource + ?Sized,
+    {
+        AsyncOutputs::new(self, input)
+    }
+
+    // Outputs made before an error are kept, and returned by the next call
+    // once the error has been dealt with.
+    pub fn run(&mut self) -> Result<ProgramState, ExecutionError> {
+        let mut queue = mem::take(&mut self.input_queue);
+        let mut outputs = mem::take(&mut self.partial_outputs);
+        let held = outputs.len();
+        let result = self.run_loop(&mut queue, &mut outputs, None, Some(held));
+        self.input_queue = queue;
+
+        let reason = match result {
+            Ok(reason) => reason.expect("No output limit was set"),
+            Err(e) => {
+                self.partial_outputs = outputs;
+                return Err(e);
+            }
+        };
+        Ok(match reason {
+            StopReason::Halted => ProgramState::Completed(outputs),
+            StopReason::PendingInput => ProgramState::PendingInput(outputs),
+            StopReason::Breakpoint(breakpoint) => ProgramState::Breakpoint(outputs, breakpoint),
+        })
+    }
+}
+
+impl From<Snapshot> for IntcodeMachine {
+    fn from(snapshot: Snapshot) -> Self {
+        let mut machine = IntcodeMachine::new(Vec::new());
+        machine.program = snapshot.memory;
+        machine.instruction_ptr = snapshot.instruction_pointer;
+        machine.relative_base = snapshot.relative_base;
+        machine.instruction_count = snapshot.instruction_count;
+        machine.input_queue = snapshot.input_queue;
+        machine
     }
 }
 
@@ -192,19 +887,52 @@ impl FromStr for IntcodeMachine {
     
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let program = s.split(",")
-            .map(|n| n.parse::<i64>())
+            .map(|n| n.trim().parse::<i64>())
             .collect::<Result<Vec<i64>, std::num::ParseIntError>>()?;
         
         Ok(IntcodeMachine::new(program))
     }
 }
 
+impl IntcodeMachine {
+    // Reads a program from a file, or from standard input if `path` is "-".
+    pub fn load(path: &str) -> Result<IntcodeMachine, LoadError> {
+        let source = if path == "-" {
+            let mut source = String::new();
+            std::io::stdin().read_to_string(&mut source).map(|_| source)
+        } else {
+            fs::read_to_string(path)
+        };
+
+        source
+            .map_err(|error| LoadError::Io {
+                path: path.to_string(),
+                error,
+            })?
+            .trim()
+            .parse()
+            .map_err(LoadError::Invalid)
+    }
+}
+
 // Backward compatibility for Days 2 and 5
 pub fn run<T>(program: &mut [i64], input: T) -> Result<Vec<i64>, ProgramError>
+where
+    T: IntoIterator<Item = i64>,
+{
+    run_with_mode(program, input, ExecutionMode::Interpreted)
+}
+
+pub fn run_with_mode<T>(
+    program: &mut [i64],
+    input: T,
+    mode: ExecutionMode,
+) -> Result<Vec<i64>, ProgramError>
 where
     T: IntoIterator<Item = i64>,
 {
     let mut machine = IntcodeMachine::new(program.to_owned());
+    machine.set_execution_mode(mode);
     machine.add_inputs(input);
 
     let result = machine.run();
@@ -215,15 +943,19 @@ where
     match result {
         Ok(ProgramState::Completed(outputs)) => Ok(outputs),
         Ok(ProgramState::PendingInput(_)) => Err(ProgramError::InsufficientInput),
-        Err(e) => Err(e),
+        Ok(ProgramState::Breakpoint(_, _)) => unreachable!("No breakpoints have been set"),
+        Err(e) => Err(e.kind),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::assembler::assemble;
     use std::iter;
 
+    const MODES: [ExecutionMode; 2] = [ExecutionMode::Interpreted, ExecutionMode::Cached];
+
     #[test]
     fn program_store_allows_any_index() {
         let mut program = ProgramStore::new();
@@ -235,138 +967,617 @@ mod tests {
 
     #[test]
     fn example1() {
-        let mut program = vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
-        let result = run(&mut program, iter::empty());
-        assert!(result.is_ok());
-        assert_eq!(program[0], 3500);
+        for &mode in &MODES {
+            let mut program = vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
+            let result = run_with_mode(&mut program, iter::empty(), mode);
+            assert!(result.is_ok());
+            assert_eq!(program[0], 3500);
+        }
     }
 
     #[test]
     fn example2() {
-        let mut program = vec![1, 0, 0, 0, 99];
-        let result = run(&mut program, iter::empty());
-        assert!(result.is_ok());
-        assert_eq!(program[0], 2);
+        for &mode in &MODES {
+            let mut program = vec![1, 0, 0, 0, 99];
+            let result = run_with_mode(&mut program, iter::empty(), mode);
+            assert!(result.is_ok());
+            assert_eq!(program[0], 2);
+        }
     }
 
     #[test]
     fn example3() {
-        let mut program = vec![2, 3, 0, 3, 99];
-        let result = run(&mut program, iter::empty());
-        assert!(result.is_ok());
-        assert_eq!(program[0], 2)
+        for &mode in &MODES {
+            let mut program = vec![2, 3, 0, 3, 99];
+            let result = run_with_mode(&mut program, iter::empty(), mode);
+            assert!(result.is_ok());
+            assert_eq!(program[0], 2)
+        }
     }
 
     #[test]
     fn example4() {
-        let mut program = vec![2, 4, 4, 5, 99, 0];
-        let result = run(&mut program, iter::empty());
-        assert!(result.is_ok());
-        assert_eq!(program[0], 2)
+        for &mode in &MODES {
+            let mut program = vec![2, 4, 4, 5, 99, 0];
+            let result = run_with_mode(&mut program, iter::empty(), mode);
+            assert!(result.is_ok());
+            assert_eq!(program[0], 2)
+        }
     }
 
     #[test]
     fn example5() {
-        let mut program = vec![1, 1, 1, 4, 99, 5, 6, 0, 99];
-        let result = run(&mut program, iter::empty());
-        assert!(result.is_ok());
-        assert_eq!(program[0], 30)
+        for &mode in &MODES {
+            let mut program = vec![1, 1, 1, 4, 99, 5, 6, 0, 99];
+            let result = run_with_mode(&mut program, iter::empty(), mode);
+            assert!(result.is_ok());
+            assert_eq!(program[0], 30)
+        }
     }
 
     #[test]
     fn day2_solution() {
-        let mut program = vec![
-            1, 31, 46, 3, 1, 1, 2, 3, 1, 3, 4, 3, 1, 5, 0, 3, 2, 10, 1, 19, 1, 6, 19, 23, 2, 23, 6,
-            27, 1, 5, 27, 31, 1, 31, 9, 35, 2, 10, 35, 39, 1, 5, 39, 43, 2, 43, 10, 47, 1, 47, 6,
-            51, 2, 51, 6, 55, 2, 55, 13, 59, 2, 6, 59, 63, 1, 63, 5, 67, 1, 6, 67, 71, 2, 71, 9,
-            75, 1, 6, 75, 79, 2, 13, 79, 83, 1, 9, 83, 87, 1, 87, 13, 91, 2, 91, 10, 95, 1, 6, 95,
-            99, 1, 99, 13, 103, 1, 13, 103, 107, 2, 107, 10, 111, 1, 9, 111, 115, 1, 115, 10, 119,
-            1, 5, 119, 123, 1, 6, 123, 127, 1, 10, 127, 131, 1, 2, 131, 135, 1, 135, 10, 0, 99, 2,
-            14, 0, 0,
-        ];
+        for &mode in &MODES {
+            let mut program = vec![
+                1, 31, 46, 3, 1, 1, 2, 3, 1, 3, 4, 3, 1, 5, 0, 3, 2, 10, 1, 19, 1, 6, 19, 23, 2, 23, 6,
+                27, 1, 5, 27, 31, 1, 31, 9, 35, 2, 10, 35, 39, 1, 5, 39, 43, 2, 43, 10, 47, 1, 47, 6,
+                51, 2, 51, 6, 55, 2, 55, 13, 59, 2, 6, 59, 63, 1, 63, 5, 67, 1, 6, 67, 71, 2, 71, 9,
+                75, 1, 6, 75, 79, 2, 13, 79, 83, 1, 9, 83, 87, 1, 87, 13, 91, 2, 91, 10, 95, 1, 6, 95,
+                99, 1, 99, 13, 103, 1, 13, 103, 107, 2, 107, 10, 111, 1, 9, 111, 115, 1, 115, 10, 119,
+                1, 5, 119, 123, 1, 6, 123, 127, 1, 10, 127, 131, 1, 2, 131, 135, 1, 135, 10, 0, 99, 2,
+                14, 0, 0,
+            ];
 
-        let result = run(&mut program, iter::empty());
-        assert!(result.is_ok());
-        assert_eq!(program[0], 1969_07_20);
+            let result = run_with_mode(&mut program, iter::empty(), mode);
+            assert!(result.is_ok());
+            assert_eq!(program[0], 1969_07_20);
+        }
     }
 
     #[test]
     fn day5_solution() {
-        let mut program = vec![
-            3, 225, 1, 225, 6, 6, 1100, 1, 238, 225, 104, 0, 1, 191, 196, 224, 1001, 224, -85, 224,
-            4, 224, 1002, 223, 8, 223, 1001, 224, 4, 224, 1, 223, 224, 223, 1101, 45, 50, 225,
-            1102, 61, 82, 225, 101, 44, 39, 224, 101, -105, 224, 224, 4, 224, 102, 8, 223, 223,
-            101, 5, 224, 224, 1, 224, 223, 223, 102, 14, 187, 224, 101, -784, 224, 224, 4, 224,
-            102, 8, 223, 223, 101, 7, 224, 224, 1, 224, 223, 223, 1001, 184, 31, 224, 1001, 224,
-            -118, 224, 4, 224, 102, 8, 223, 223, 1001, 224, 2, 224, 1, 223, 224, 223, 1102, 91, 18,
-            225, 2, 35, 110, 224, 101, -810, 224, 224, 4, 224, 102, 8, 223, 223, 101, 3, 224, 224,
-            1, 223, 224, 223, 1101, 76, 71, 224, 1001, 224, -147, 224, 4, 224, 102, 8, 223, 223,
-            101, 2, 224, 224, 1, 224, 223, 223, 1101, 7, 16, 225, 1102, 71, 76, 224, 101, -5396,
-            224, 224, 4, 224, 1002, 223, 8, 223, 101, 5, 224, 224, 1, 224, 223, 223, 1101, 72, 87,
-            225, 1101, 56, 77, 225, 1102, 70, 31, 225, 1102, 29, 15, 225, 1002, 158, 14, 224, 1001,
-            224, -224, 224, 4, 224, 102, 8, 223, 223, 101, 1, 224, 224, 1, 223, 224, 223, 4, 223,
-            99, 0, 0, 0, 677, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1105, 0, 99999, 1105, 227, 247,
-            1105, 1, 99999, 1005, 227, 99999, 1005, 0, 256, 1105, 1, 99999, 1106, 227, 99999, 1106,
-            0, 265, 1105, 1, 99999, 1006, 0, 99999, 1006, 227, 274, 1105, 1, 99999, 1105, 1, 280,
-            1105, 1, 99999, 1, 225, 225, 225, 1101, 294, 0, 0, 105, 1, 0, 1105, 1, 99999, 1106, 0,
-            300, 1105, 1, 99999, 1, 225, 225, 225, 1101, 314, 0, 0, 106, 0, 0, 1105, 1, 99999,
-            1007, 226, 226, 224, 1002, 223, 2, 223, 1006, 224, 329, 1001, 223, 1, 223, 8, 226, 677,
-            224, 1002, 223, 2, 223, 1005, 224, 344, 1001, 223, 1, 223, 107, 226, 677, 224, 1002,
-            223, 2, 223, 1006, 224, 359, 1001, 223, 1, 223, 8, 677, 677, 224, 1002, 223, 2, 223,
-            1005, 224, 374, 1001, 223, 1, 223, 1108, 226, 226, 224, 1002, 223, 2, 223, 1005, 224,
-            389, 1001, 223, 1, 223, 7, 677, 226, 224, 1002, 223, 2, 223, 1005, 224, 404, 101, 1,
-            223, 223, 7, 226, 226, 224, 102, 2, 223, 223, 1006, 224, 419, 1001, 223, 1, 223, 1108,
-            226, 677, 224, 102, 2, 223, 223, 1005, 224, 434, 1001, 223, 1, 223, 1107, 226, 226,
-            224, 1002, 223, 2, 223, 1006, 224, 449, 1001, 223, 1, 223, 1007, 677, 677, 224, 102, 2,
-            223, 223, 1006, 224, 464, 1001, 223, 1, 223, 107, 226, 226, 224, 1002, 223, 2, 223,
-            1005, 224, 479, 101, 1, 223, 223, 1107, 677, 226, 224, 1002, 223, 2, 223, 1005, 224,
-            494, 1001, 223, 1, 223, 1008, 677, 677, 224, 102, 2, 223, 223, 1005, 224, 509, 101, 1,
-            223, 223, 107, 677, 677, 224, 102, 2, 223, 223, 1005, 224, 524, 1001, 223, 1, 223,
-            1108, 677, 226, 224, 1002, 223, 2, 223, 1005, 224, 539, 1001, 223, 1, 223, 7, 226, 677,
-            224, 102, 2, 223, 223, 1006, 224, 554, 1001, 223, 1, 223, 8, 677, 226, 224, 1002, 223,
-            2, 223, 1006, 224, 569, 101, 1, 223, 223, 108, 226, 226, 224, 1002, 223, 2, 223, 1006,
-            224, 584, 1001, 223, 1, 223, 1107, 226, 677, 224, 1002, 223, 2, 223, 1006, 224, 599,
-            101, 1, 223, 223, 1008, 226, 226, 224, 102, 2, 223, 223, 1005, 224, 614, 1001, 223, 1,
-            223, 1007, 226, 677, 224, 1002, 223, 2, 223, 1006, 224, 629, 1001, 223, 1, 223, 108,
-            677, 226, 224, 102, 2, 223, 223, 1005, 224, 644, 101, 1, 223, 223, 1008, 226, 677, 224,
-            1002, 223, 2, 223, 1005, 224, 659, 101, 1, 223, 223, 108, 677, 677, 224, 1002, 223, 2,
-            223, 1006, 224, 674, 1001, 223, 1, 223, 4, 223, 99, 226,
-        ];
+        for &mode in &MODES {
+            let mut program = vec![
+                3, 225, 1, 225, 6, 6, 1100, 1, 238, 225, 104, 0, 1, 191, 196, 224, 1001, 224, -85, 224,
+                4, 224, 1002, 223, 8, 223, 1001, 224, 4, 224, 1, 223, 224, 223, 1101, 45, 50, 225,
+                1102, 61, 82, 225, 101, 44, 39, 224, 101, -105, 224, 224, 4, 224, 102, 8, 223, 223,
+                101, 5, 224, 224, 1, 224, 223, 223, 102, 14, 187, 224, 101, -784, 224, 224, 4, 224,
+                102, 8, 223, 223, 101, 7, 224, 224, 1, 224, 223, 223, 1001, 184, 31, 224, 1001, 224,
+                -118, 224, 4, 224, 102, 8, 223, 223, 1001, 224, 2, 224, 1, 223, 224, 223, 1102, 91, 18,
+                225, 2, 35, 110, 224, 101, -810, 224, 224, 4, 224, 102, 8, 223, 223, 101, 3, 224, 224,
+                1, 223, 224, 223, 1101, 76, 71, 224, 1001, 224, -147, 224, 4, 224, 102, 8, 223, 223,
+                101, 2, 224, 224, 1, 224, 223, 223, 1101, 7, 16, 225, 1102, 71, 76, 224, 101, -5396,
+                224, 224, 4, 224, 1002, 223, 8, 223, 101, 5, 224, 224, 1, 224, 223, 223, 1101, 72, 87,
+                225, 1101, 56, 77, 225, 1102, 70, 31, 225, 1102, 29, 15, 225, 1002, 158, 14, 224, 1001,
+                224, -224, 224, 4, 224, 102, 8, 223, 223, 101, 1, 224, 224, 1, 223, 224, 223, 4, 223,
+                99, 0, 0, 0, 677, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1105, 0, 99999, 1105, 227, 247,
+                1105, 1, 99999, 1005, 227, 99999, 1005, 0, 256, 1105, 1, 99999, 1106, 227, 99999, 1106,
+                0, 265, 1105, 1, 99999, 1006, 0, 99999, 1006, 227, 274, 1105, 1, 99999, 1105, 1, 280,
+                1105, 1, 99999, 1, 225, 225, 225, 1101, 294, 0, 0, 105, 1, 0, 1105, 1, 99999, 1106, 0,
+                300, 1105, 1, 99999, 1, 225, 225, 225, 1101, 314, 0, 0, 106, 0, 0, 1105, 1, 99999,
+                1007, 226, 226, 224, 1002, 223, 2, 223, 1006, 224, 329, 1001, 223, 1, 223, 8, 226, 677,
+                224, 1002, 223, 2, 223, 1005, 224, 344, 1001, 223, 1, 223, 107, 226, 677, 224, 1002,
+                223, 2, 223, 1006, 224, 359, 1001, 223, 1, 223, 8, 677, 677, 224, 1002, 223, 2, 223,
+                1005, 224, 374, 1001, 223, 1, 223, 1108, 226, 226, 224, 1002, 223, 2, 223, 1005, 224,
+                389, 1001, 223, 1, 223, 7, 677, 226, 224, 1002, 223, 2, 223, 1005, 224, 404, 101, 1,
+                223, 223, 7, 226, 226, 224, 102, 2, 223, 223, 1006, 224, 419, 1001, 223, 1, 223, 1108,
+                226, 677, 224, 102, 2, 223, 223, 1005, 224, 434, 1001, 223, 1, 223, 1107, 226, 226,
+                224, 1002, 223, 2, 223, 1006, 224, 449, 1001, 223, 1, 223, 1007, 677, 677, 224, 102, 2,
+                223, 223, 1006, 224, 464, 1001, 223, 1, 223, 107, 226, 226, 224, 1002, 223, 2, 223,
+                1005, 224, 479, 101, 1, 223, 223, 1107, 677, 226, 224, 1002, 223, 2, 223, 1005, 224,
+                494, 1001, 223, 1, 223, 1008, 677, 677, 224, 102, 2, 223, 223, 1005, 224, 509, 101, 1,
+                223, 223, 107, 677, 677, 224, 102, 2, 223, 223, 1005, 224, 524, 1001, 223, 1, 223,
+                1108, 677, 226, 224, 1002, 223, 2, 223, 1005, 224, 539, 1001, 223, 1, 223, 7, 226, 677,
+                224, 102, 2, 223, 223, 1006, 224, 554, 1001, 223, 1, 223, 8, 677, 226, 224, 1002, 223,
+                2, 223, 1006, 224, 569, 101, 1, 223, 223, 108, 226, 226, 224, 1002, 223, 2, 223, 1006,
+                224, 584, 1001, 223, 1, 223, 1107, 226, 677, 224, 1002, 223, 2, 223, 1006, 224, 599,
+                101, 1, 223, 223, 1008, 226, 226, 224, 102, 2, 223, 223, 1005, 224, 614, 1001, 223, 1,
+                223, 1007, 226, 677, 224, 1002, 223, 2, 223, 1006, 224, 629, 1001, 223, 1, 223, 108,
+                677, 226, 224, 102, 2, 223, 223, 1005, 224, 644, 101, 1, 223, 223, 1008, 226, 677, 224,
+                1002, 223, 2, 223, 1005, 224, 659, 101, 1, 223, 223, 108, 677, 677, 224, 1002, 223, 2,
+                223, 1006, 224, 674, 1001, 223, 1, 223, 4, 223, 99, 226,
+            ];
 
-        let result = run(&mut program, iter::once(5)).unwrap();
-        assert_eq!(result, vec![4283952]);
+            let result = run_with_mode(&mut program, iter::once(5), mode).unwrap();
+            assert_eq!(result, vec![4283952]);
+        }
     }
 
     #[test]
-    fn day9_example1() {
-        let quine = vec![
-            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
-        ];
-        let mut machine = IntcodeMachine::new(quine.clone());
-        let result = machine.run();
-        assert_eq!(result, Ok(ProgramState::Completed(quine)));
+    fn step_reports_writes_and_outputs() {
+        let mut machine = IntcodeMachine::with_seed(vec![3, 0, 4, 0, 99], 42);
+
+        let step = machine.step().unwrap();
+        assert_eq!(step.instruction, Instruction::Input(Argument::Position(0)));
+        assert_eq!(step.write, Some(MemoryWrite { address: 0, value: 42 }));
+        assert_eq!(step.output, None);
+        assert_eq!(step.status, StepStatus::Running);
+        assert_eq!(machine.instruction_pointer(), 2);
+
+        let step = machine.step().unwrap();
+        assert_eq!(step.address, 2);
+        assert_eq!(step.output, Some(42));
+        assert_eq!(step.write, None);
+
+        let step = machine.step().unwrap();
+        assert_eq!(step.status, StepStatus::Halted);
+        assert_eq!(machine.instruction_pointer(), 4);
     }
 
     #[test]
-    fn day9_example2() {
-        let program = vec![1102, 34915192, 34915192, 7, 4, 7, 99, 0];
-        let mut machine = IntcodeMachine::new(program);
-        let result = match machine.run().unwrap() {
-            ProgramState::Completed(mut numbers) => numbers.pop().unwrap(),
-            ProgramState::PendingInput(_) => panic!("Not completed"),
-        };
+    fn step_blocks_on_input_without_moving() {
+        let mut machine = IntcodeMachine::new(vec![109, 5, 3, 0, 99]);
+
+        machine.step().unwrap();
+        assert_eq!(machine.relative_base(), 5);
+
+        let step = machine.step().unwrap();
+        assert_eq!(step.status, StepStatus::BlockedOnInput);
+        assert_eq!(machine.instruction_pointer(), 2);
 
-        let s = format!("{}", result);
-        assert_eq!(s.chars().count(), 16);
+        machine.add_input(7);
+        let step = machine.step().unwrap();
+        assert_eq!(step.status, StepStatus::Running);
+        assert_eq!(step.write, Some(MemoryWrite { address: 0, value: 7 }));
     }
 
     #[test]
-    fn day9_example3() {
-        let program = vec![104, 1125899906842624, 99];
+    fn address_breakpoint_stops_before_instruction_and_resumes() {
+        let mut machine = IntcodeMachine::new(vec![104, 1, 104, 2, 104, 3, 99]);
+        machine.add_breakpoint(Breakpoint::Address(4));
+
+        assert_eq!(
+            machine.run(),
+            Ok(ProgramState::Breakpoint(vec![1, 2], Breakpoint::Address(4)))
+        );
+        assert_eq!(machine.instruction_pointer(), 4);
+        assert_eq!(machine.run(), Ok(ProgramState::Completed(vec![3])));
+    }
+
+    #[test]
+    fn opcode_breakpoint_stops_at_every_input() {
+        let mut machine = IntcodeMachine::new(vec![3, 9, 3, 10, 4, 9, 4, 10, 99]);
+        machine.add_inputs(vec![5, 6]);
+        machine.add_breakpoint(Breakpoint::Opcode(3));
+
+        let expected = ProgramState::Breakpoint(vec![], Breakpoint::Opcode(3));
+        assert_eq!(machine.run(), Ok(expected));
+        assert_eq!(machine.instruction_pointer(), 0);
+
+        let expected = ProgramState::Breakpoint(vec![], Breakpoint::Opcode(3));
+        assert_eq!(machine.run(), Ok(expected));
+        assert_eq!(machine.instruction_pointer(), 2);
+
+        assert_eq!(machine.run(), Ok(ProgramState::Completed(vec![5, 6])));
+    }
+
+    #[test]
+    fn opcode_breakpoint_survives_pending_input() {
+        let mut machine = IntcodeMachine::new(vec![3, 5, 4, 5, 99, 0]);
+        machine.add_breakpoint(Breakpoint::Opcode(3));
+
+        let expected = ProgramState::Breakpoint(vec![], Breakpoint::Opcode(3));
+        assert_eq!(machine.run(), Ok(expected));
+        assert_eq!(machine.run(), Ok(ProgramState::PendingInput(vec![])));
+
+        machine.add_input(8);
+        assert_eq!(machine.run(), Ok(ProgramState::Completed(vec![8])));
+    }
+
+    #[test]
+    fn watchpoints_stop_after_access() {
+        let program = vec![1001, 9, 1, 9, 4, 9, 4, 10, 99, 41, 7];
         let mut machine = IntcodeMachine::new(program);
+        machine.add_breakpoint(Breakpoint::Write(9));
+        machine.add_breakpoint(Breakpoint::Read(10));
+
+        assert_eq!(
+            machine.run(),
+            Ok(ProgramState::Breakpoint(vec![], Breakpoint::Write(9)))
+        );
+        assert_eq!(machine.instruction_pointer(), 4);
+
         assert_eq!(
             machine.run(),
-            Ok(ProgramState::Completed(vec![1125899906842624]))
+            Ok(ProgramState::Breakpoint(vec![42, 7], Breakpoint::Read(10)))
+        );
+
+        assert!(machine.remove_breakpoint(&Breakpoint::Read(10)));
+        assert_eq!(machine.run(), Ok(ProgramState::Completed(vec![])));
+    }
+
+    #[test]
+    fn cached_mode_sees_self_modifying_code() {
+        let program = assemble(
+            "
+            loop:   OUT 5
+                    ADD [count], 1, [count]
+                    ADD 0, 7, [loop+1]      ; rewrite the OUT operand
+                    LT [count], 2, [flag]
+                    JT [flag], loop
+                    HLT
+            count:  DATA 0
+            flag:   DATA 0
+            ",
+        )
+        .unwrap();
+
+        for &mode in &MODES {
+            let mut machine = IntcodeMachine::new(program.clone());
+            machine.set_execution_mode(mode);
+            assert_eq!(machine.run(), Ok(ProgramState::Completed(vec![5, 7])));
+        }
+    }
+
+    #[test]
+    fn cached_mode_allows_writes_to_negative_addresses() {
+        for &mode in &MODES {
+            let mut machine = IntcodeMachine::new(vec![1101, 1, 1, -1, 104, 7, 99]);
+            machine.set_execution_mode(mode);
+            assert_eq!(machine.run(), Ok(ProgramState::Completed(vec![7])));
+        }
+    }
+
+    #[test]
+    fn errors_report_where_they_happened() {
+        // ADD, ARB, then a jump into data.
+        let mut machine = IntcodeMachine::new(vec![1101, 1, 2, 20, 109, 5, 1105, 1, 9, 42]);
+        let error = machine.run().unwrap_err();
+        assert_eq!(
+            error.context,
+            ErrorContext {
+                instruction_pointer: 9,
+                instruction: 42,
+                relative_base: 5,
+                instruction_count: 3,
+                recent_addresses: vec![0, 4, 6],
+            }
         );
+        assert_eq!(
+            error.to_string(),
+            "Unknown opcode: 42 at address 9 (instruction 42, relative base 5, \
+             after 3 instructions; came from 0, 4, 6)"
+        );
+
+        match machine.step() {
+            Err(ExecutionError {
+                kind: ProgramError::UnknownOpcode(opcode),
+                ..
+            }) => assert_eq!(opcode, 42),
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn errors_keep_only_recent_addresses() {
+        let mut program = [109, 0].repeat(10);
+        program.push(0);
+        let error = IntcodeMachine::new(program).run().unwrap_err();
+        assert_eq!(error.context.instruction_count, 10);
+        assert_eq!(
+            error.context.recent_addresses,
+            vec![4, 6, 8, 10, 12, 14, 16, 18]
+        );
+    }
+
+    #[test]
+    fn strict_mode_rejects_what_lenient_mode_reinterprets() {
+        let cases: Vec<(Vec<i64>, ProgramError)> = vec![
+            (
+                vec![11101, 1, 2, 5, 99, 0],
+                ProgramError::ImmediateDestination(5),
+            ),
+            (vec![1, -1, 0, 0, 99], ProgramError::NegativeAddress(-1)),
+            (vec![109, -5, 204, 2, 99], ProgramError::NegativeAddress(-3)),
+            (vec![199], ProgramError::UnusedParameterMode(199)),
+            (vec![10004, 0, 99], ProgramError::UnusedParameterMode(10004)),
+            (vec![1105, 1, -1], ProgramError::NegativeJump(-1)),
+        ];
+
+        for (program, kind) in cases {
+            let mut lenient = IntcodeMachine::new(program.clone());
+            assert_eq!(lenient.strictness(), Strictness::Lenient);
+            if kind != ProgramError::NegativeJump(-1) {
+                assert!(lenient.run().is_ok(), "{:?}", program);
+            }
+
+            let mut strict = IntcodeMachine::new(program.clone());
+            strict.set_strictness(Strictness::Strict);
+            let error = strict.run().unwrap_err();
+            assert_eq!(error.kind, kind);
+            assert_eq!(strict.memory()[0], program[0]);
+        }
+    }
+
+    #[test]
+    fn strict_mode_allows_jumps_not_taken() {
+        let mut machine = IntcodeMachine::new(vec![1105, 0, -1, 104, 7, 99]);
+        machine.set_strictness(Strictness::Strict);
+        assert_eq!(machine.run(), Ok(ProgramState::Completed(vec![7])));
+    }
+
+    #[test]
+    fn arithmetic_overflow() {
+        // Squares a value twice, then outputs it.
+        let program = vec![1101, 0, 0, 14, 2, 14, 14, 14, 2, 14, 14, 14, 4, 14];
+        let run = |arithmetic, value| {
+            let mut program = program.clone();
+            program[1] = value;
+            let mut machine = IntcodeMachine::new(program);
+            machine.set_arithmetic(arithmetic);
+            machine.run_until_output()
+        };
+
+        for &arithmetic in &[
+            Arithmetic::Wrapping,
+            Arithmetic::Checked,
+            Arithmetic::Saturating,
+        ] {
+            assert_eq!(
+                run(arithmetic, 50_000),
+                Ok(OutputEvent::Output(6_250_000_000_000_000_000))
+            );
+        }
+        assert_eq!(
+            IntcodeMachine::new(vec![]).arithmetic(),
+            Arithmetic::Wrapping
+        );
+        assert_eq!(
+            run(Arithmetic::Wrapping, 4_294_967_296),
+            Ok(OutputEvent::Output(0))
+        );
+        assert_eq!(
+            run(Arithmetic::Saturating, -4_294_967_296),
+            Ok(OutputEvent::Output(i64::MAX))
+        );
+
+        let error = run(Arithmetic::Checked, 4_294_967_296).unwrap_err();
+        assert_eq!(error.kind, ProgramError::Overflow(1 << 32, 1 << 32));
+        assert_eq!(error.context.instruction_pointer, 4);
+    }
+
+    #[test]
+    fn relative_base_overflow() {
+        let mut machine = IntcodeMachine::new(vec![109, i64::MAX, 109, 1, 99]);
+        machine.set_arithmetic(Arithmetic::Checked);
+        let error = machine.run().unwrap_err();
+        assert_eq!(error.kind, ProgramError::Overflow(i64::MAX, 1));
+        assert_eq!(machine.relative_base(), i64::MAX);
+    }
+
+    // Counts [20] down from 50 to 0, outputting every value, then halts.
+    fn countdown() -> Vec<i64> {
+        vec![1101, 0, 50, 20, 4, 20, 1001, 20, -1, 20, 1005, 20, 4, 99]
+    }
+
+    #[test]
+    fn instruction_limit_leaves_the_machine_resumable() {
+        let expected: Vec<i64> = (1..=50).rev().collect();
+        let mut machine = IntcodeMachine::new(countdown());
+        machine.set_limits(Limits {
+            max_instructions: Some(10),
+            ..Limits::default()
+        });
+
+        let error = machine.run().unwrap_err();
+        assert_eq!(error.kind, ProgramError::InstructionLimit(10));
+        assert_eq!(machine.instruction_count(), 10);
+
+        machine.set_limits(Limits::default());
+        match machine.run() {
+            Ok(ProgramState::Completed(outputs)) => assert_eq!(outputs, expected),
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn memory_limits() {
+        let program = vec![1101, 1, 1, 1_000_000, 99];
+        let run = |limits| {
+            let mut machine = IntcodeMachine::new(program.clone());
+            machine.set_limits(limits);
+            let result = machine.run().map_err(|e| e.kind);
+            (result, machine.memory().cell_count())
+        };
+
+        let cells = Limits {
+            max_cells: Some(5),
+            ..Limits::default()
+        };
+        assert_eq!(run(cells), (Err(ProgramError::MemoryLimit(5)), 5));
+        let cells = Limits {
+            max_cells: Some(6),
+            ..Limits::default()
+        };
+        assert_eq!(run(cells).1, 6);
+
+        let address = Limits {
+            max_address: Some(1000),
+            ..Limits::default()
+        };
+        assert_eq!(run(address).0, Err(ProgramError::AddressLimit(1_000_000)));
+
+        let mut machine = IntcodeMachine::new(vec![1105, 1, 5000]);
+        machine.set_limits(address);
+        assert_eq!(
+            machine.run().map_err(|e| e.kind),
+            Err(ProgramError::AddressLimit(5000))
+        );
+
+        let mut machine = IntcodeMachine::new(vec![1105, 1, 0]);
+        machine.set_limits(Limits {
+            max_address: Some(1),
+            ..Limits::default()
+        });
+        assert_eq!(
+            machine.run().map_err(|e| e.kind),
+            Err(ProgramError::AddressLimit(2))
+        );
+    }
+
+    #[test]
+    fn output_limit_only_applies_to_buffers() {
+        let limits = Limits {
+            max_buffered_outputs: Some(20),
+            ..Limits::default()
+        };
+        let mut machine = IntcodeMachine::new(countdown());
+        machine.set_limits(limits);
+        let error = machine.run().unwrap_err();
+        assert_eq!(error.kind, ProgramError::OutputLimit(20));
+        assert_eq!(error.context.instruction_pointer, 4);
+
+        let mut machine = IntcodeMachine::new(countdown());
+        machine.set_limits(limits);
+        let mut outputs = Vec::new();
+        let stop = machine.run_with(&mut VecDeque::new(), &mut outputs);
+        assert_eq!(stop, Ok(StopReason::Halted));
+        assert_eq!(outputs.len(), 50);
+    }
+
+    #[test]
+    fn deadline_stops_endless_loops() {
+        let mut machine = IntcodeMachine::new(vec![1105, 1, 0]);
+        machine.set_limits(Limits {
+            deadline: Some(Instant::now() + std::time::Duration::from_millis(10)),
+            ..Limits::default()
+        });
+        let error = machine.run().unwrap_err();
+        assert_eq!(error.kind, ProgramError::DeadlineExceeded);
+        assert_eq!(error.context.instruction_count % DEADLINE_INTERVAL, 0);
+    }
+
+    #[test]
+    fn infinite_loops_are_detected() {
+        let detect = |program: Vec<i64>| {
+            let mut machine = IntcodeMachine::new(program);
+            machine.set_loop_detection(true);
+            machine.set_limits(Limits {
+                max_instructions: Some(10_000),
+                ..Limits::default()
+            });
+            machine.run().map_err(|e| e.kind)
+        };
+
+        assert_eq!(
+            detect(vec![1105, 1, 0]),
+            Err(ProgramError::InfiniteLoop {
+                entry: 0,
+                period: 1
+            })
+        );
+        // Flips [12] between 0 and 1 forever.
+        assert_eq!(
+            detect(vec![1002, 12, -1, 12, 1001, 12, 1, 12, 1105, 1, 0, 99, 0]),
+            Err(ProgramError::InfiniteLoop {
+                entry: 0,
+                period: 6
+            })
+        );
+        // Terminating loops and loops with output are left alone.
+        assert_eq!(
+            detect(countdown()),
+            Ok(ProgramState::Completed((1..=50).rev().collect()))
+        );
+        assert_eq!(
+            detect(vec![104, 1, 1105, 1, 0]),
+            Err(ProgramError::InstructionLimit(10_000))
+        );
+    }
+
+    #[test]
+    fn loop_detection_follows_outside_changes() {
+        // Spins until [7] is non-zero.
+        let program = vec![1006, 7, 0, 104, 1, 99, 0, 0];
+        let mut machine = IntcodeMachine::new(program);
+        machine.set_loop_detection(true);
+        let snapshot = machine.snapshot();
+        let looped = Err(ProgramError::InfiniteLoop {
+            entry: 0,
+            period: 1,
+        });
+        assert_eq!(machine.run().map_err(|e| e.kind), looped);
+
+        // Memory has changed, so the next state hasn't been seen before and
+        // the loop has to be seen twice more to be reported.
+        machine.poke(6, 5);
+        let error = machine.run().unwrap_err();
+        assert_eq!(Err(error.kind), looped);
+        assert_eq!(error.context.instruction_count, 6);
+
+        machine.restore(&snapshot);
+        machine.poke(7, 1);
+        assert_eq!(machine.run(), Ok(ProgramState::Completed(vec![1])));
+    }
+
+    #[test]
+    fn load_program_from_file() {
+        let path = std::env::temp_dir().join(format!(
+            "intcode_load_program_from_file_{}.txt",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+
+        std::fs::write(path, "104,7,99\n").unwrap();
+        let machine = IntcodeMachine::load(path);
+        std::fs::write(path, "104,x,99").unwrap();
+        let invalid = IntcodeMachine::load(path);
+        std::fs::remove_file(path).unwrap();
+
+        let message = |result: Result<_, LoadError>| result.err().unwrap().to_string();
+        assert_eq!(machine.unwrap().run(), Ok(ProgramState::Completed(vec![7])));
+        assert!(message(invalid).starts_with("Invalid program"));
+        assert!(message(IntcodeMachine::load(path)).starts_with(&format!("Cannot read {}", path)));
+    }
+
+    #[test]
+    fn parse_program_with_whitespace() {
+        let machine: IntcodeMachine = "1, 0,0,0,\n99\n".parse().unwrap();
+        assert_eq!(machine.memory().len(), 5);
+        assert_eq!(machine.memory()[4], 99);
+    }
+
+    #[test]
+    fn day9_example1() {
+        for &mode in &MODES {
+            let quine = vec![
+                109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+            ];
+            let mut machine = IntcodeMachine::new(quine.clone());
+            machine.set_execution_mode(mode);
+            let result = machine.run();
+            assert_eq!(result, Ok(ProgramState::Completed(quine)));
+        }
+    }
+
+    #[test]
+    fn day9_example2() {
+        for &mode in &MODES {
+            let program = vec![1102, 34915192, 34915192, 7, 4, 7, 99, 0];
+            let mut machine = IntcodeMachine::new(program);
+            machine.set_execution_mode(mode);
+            let result = match machine.run().unwrap() {
+                ProgramState::Completed(mut numbers) => numbers.pop().unwrap(),
+                ProgramState::PendingInput(_) | ProgramState::Breakpoint(_, _) => {
+                    panic!("Not completed")
+                }
+            };
+
+            let s = format!("{}", result);
+            assert_eq!(s.chars().count(), 16);
+        }
+    }
+
+    #[test]
+    fn day9_example3() {
+        for &mode in &MODES {
+            let program = vec![104, 1125899906842624, 99];
+            let mut machine = IntcodeMachine::new(program);
+            machine.set_execution_mode(mode);
+            assert_eq!(
+                machine.run(),
+                Ok(ProgramState::Completed(vec![1125899906842624]))
+            );
+        }
     }
 }
@@ -0,0 +1,20 @@
+use std::time::Instant;
+
+// Bounds on what a machine may do, for running programs which can't be
+// trusted to behave. Every limit is off by default.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Limits {
+    // Counted over the machine's whole life, as `instruction_count` is. The
+    // machine stops before the instruction which would go over, so raising
+    // the limit lets it carry on.
+    pub max_instructions: Option<u64>,
+    // Distinct cells loaded or written, including the loaded program.
+    pub max_cells: Option<usize>,
+    // The highest address which may be executed, read or written.
+    pub max_address: Option<usize>,
+    // Outputs which `run` and `run_until_outputs` may collect before
+    // returning them. Outputs sent straight to a sink are not limited.
+    pub max_buffered_outputs: Option<usize>,
+    // Only checked every so many instructions, so may be overrun slightly.
+    pub deadline: Option<Instant>,
+}
@@ -0,0 +1,194 @@
+use crate::errors::ProgramError;
+use crate::ProgramStore;
+use std::collections::HashMap;
+
+// Once this many states have been remembered they are forgotten and
+// recording starts again. A loop shorter than this is still found, just a
+// little later.
+const MAX_STATES: usize = 1 << 20;
+
+// Watches for a machine coming back to a state it has been in before,
+// without any input or output in between. It would then go round the same
+// states forever.
+//
+// States are only recorded where a jump lands, since any loop has to jump
+// back to its start. Each is reduced to a 64-bit fingerprint of the
+// instruction pointer, the relative base and a hash of memory which is
+// updated as cells are written, so a check doesn't depend on the size of
+// memory.
+//
+// Different states can share a fingerprint, so a repeated one only makes
+// the state a suspect. The loop is reported once the machine is back in
+// exactly that state, compared in full, one period later. A report is
+// therefore never a false alarm, but comes one time round the loop late.
+#[derive(Clone, Debug, Default)]
+pub struct LoopDetector {
+    memory_hash: u64,
+    // Fingerprint to the instruction count when it was seen.
+    seen: HashMap<u64, u64>,
+    suspect: Option<Suspect>,
+}
+
+#[derive(Clone, Debug)]
+struct Suspect {
+    fingerprint: u64,
+    instruction_ptr: usize,
+    relative_base: i64,
+    cells: Vec<(usize, i64)>,
+    instruction_count: u64,
+    period: u64,
+}
+
+// Memory's contents, leaving out cells holding 0 as the hash does.
+fn nonzero_cells(memory: &ProgramStore) -> Vec<(usize, i64)> {
+    let mut cells: Vec<(usize, i64)> = memory.cells().filter(|&(_, value)| value != 0).collect();
+    cells.sort_unstable();
+    cells
+}
+
+// The finaliser from SplitMix64.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+// Cells holding 0 leave the hash alone, as memory reads 0 everywhere until
+// it is written.
+fn cell_hash(address: usize, value: i64) -> u64 {
+    if value == 0 {
+        0
+    } else {
+        mix(address as u64 ^ mix(value as u64))
+    }
+}
+
+impl LoopDetector {
+    pub fn new(memory: &ProgramStore) -> LoopDetector {
+        let mut detector = LoopDetector::default();
+        detector.reset(memory);
+        detector
+    }
+
+    // Starts again from scratch, with `memory` replaced wholesale.
+    pub fn reset(&mut self, memory: &ProgramStore) {
+        self.memory_hash = memory
+            .cells()
+            .fold(0, |hash, (address, value)| hash ^ cell_hash(address, value));
+        self.forget_states();
+    }
+
+    pub fn record_write(&mut self, address: usize, old: i64, new: i64) {
+        self.memory_hash ^= cell_hash(address, old) ^ cell_hash(address, new);
+    }
+
+    // Input or output makes any earlier state safe to revisit.
+    pub fn forget_states(&mut self) {
+        self.seen.clear();
+        self.suspect = None;
+    }
+
+    // Records the state the machine has just jumped to, failing if it is
+    // going round a loop.
+    pub fn visit(
+        &mut self,
+        memory: &ProgramStore,
+        instruction_ptr: usize,
+        relative_base: i64,
+        instruction_count: u64,
+    ) -> Result<(), ProgramError> {
+        let fingerprint =
+            mix(self.memory_hash ^ mix(instruction_ptr as u64 ^ mix(relative_base as u64)));
+
+        if let Some(suspect) = self.suspect.take() {
+            let due = suspect.instruction_count + suspect.period;
+            if instruction_count < due {
+                self.suspect = Some(suspect);
+            } else if instruction_count == due
+                && fingerprint == suspect.fingerprint
+                && instruction_ptr == suspect.instruction_ptr
+                && relative_base == suspect.relative_base
+                && nonzero_cells(memory) == suspect.cells
+            {
+                return Err(ProgramError::InfiniteLoop {
+                    entry: instruction_ptr,
+                    period: suspect.period,
+                });
+            }
+            // Otherwise the fingerprints only matched by chance.
+        }
+
+        if let Some(&count) = self.seen.get(&fingerprint) {
+            if self.suspect.is_none() {
+                self.suspect = Some(Suspect {
+                    fingerprint,
+                    instruction_ptr,
+                    relative_base,
+                    cells: nonzero_cells(memory),
+                    instruction_count,
+                    period: instruction_count - count,
+                });
+            }
+        }
+
+        if self.seen.len() >= MAX_STATES {
+            self.seen.clear();
+        }
+        self.seen.insert(fingerprint, instruction_count);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_hash_is_kept_up_to_date() {
+        let mut memory: ProgramStore = vec![1, 2, 3].into_iter().collect();
+        let mut detector = LoopDetector::new(&memory);
+
+        for &(address, value) in &[(1, 5), (10, 7), (1_000_000, 9), (0, 0), (10, 0)] {
+            detector.record_write(address, memory[address], value);
+            memory[address] = value;
+        }
+        assert_eq!(detector.memory_hash, LoopDetector::new(&memory).memory_hash);
+
+        // The same contents, laid out differently.
+        let mut other: ProgramStore = vec![0, 5, 3].into_iter().collect();
+        other[1_000_000] = 9;
+        assert_eq!(detector.memory_hash, LoopDetector::new(&other).memory_hash);
+    }
+
+    #[test]
+    fn revisits_are_reported_one_period_later() {
+        let memory: ProgramStore = vec![1105, 1, 0].into_iter().collect();
+        let mut detector = LoopDetector::new(&memory);
+        assert_eq!(detector.visit(&memory, 0, 0, 1), Ok(()));
+        assert_eq!(detector.visit(&memory, 0, 1, 2), Ok(()));
+        assert_eq!(detector.visit(&memory, 0, 0, 4), Ok(()));
+        assert_eq!(detector.visit(&memory, 0, 1, 5), Ok(()));
+        assert_eq!(
+            detector.visit(&memory, 0, 0, 7),
+            Err(ProgramError::InfiniteLoop {
+                entry: 0,
+                period: 3
+            })
+        );
+
+        detector.forget_states();
+        assert_eq!(detector.visit(&memory, 0, 0, 8), Ok(()));
+    }
+
+    #[test]
+    fn matching_fingerprints_are_checked() {
+        let memory: ProgramStore = vec![1105, 1, 0].into_iter().collect();
+        let mut detector = LoopDetector::new(&memory);
+        assert_eq!(detector.visit(&memory, 0, 0, 1), Ok(()));
+        assert_eq!(detector.visit(&memory, 0, 0, 2), Ok(()));
+
+        // Pretend that a different memory gave the same hash.
+        let other: ProgramStore = vec![1105, 1, 0, 7].into_iter().collect();
+        assert_eq!(detector.visit(&other, 0, 0, 3), Ok(()));
+    }
+}
@@ -0,0 +1,573 @@
+use crate::errors::{ExecutionError, NetworkError};
+use crate::{IntcodeMachine, StepStatus};
+use std::collections::VecDeque;
+use std::fmt;
+
+// The address Day 23's NAT listens on.
+pub const NAT_ADDRESS: i64 = 255;
+
+// Nodes must see this many consecutive rounds in which nothing was sent and
+// every receive queue was empty before the network counts as idle.
+const DEFAULT_IDLE_ROUNDS: usize = 2;
+
+// How many instructions a node may execute in one round, so that a node
+// which never asks for input can't hold up the rest.
+const DEFAULT_STEP_BUDGET: usize = 1_000_000;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Packet {
+    pub source: i64,
+    pub destination: i64,
+    pub x: i64,
+    pub y: i64,
+}
+
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} -> {}: ({}, {})",
+            self.source, self.destination, self.x, self.y
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NetworkEvent {
+    Sent(Packet),
+    Idle,
+    Injected(Packet),
+    Halted(i64),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LogEntry {
+    pub round: usize,
+    pub event: NetworkEvent,
+}
+
+// Listens on a special address outside the range of node addresses. Packets
+// sent there are handed to `receive`; once the network goes idle, `on_idle`
+// may return a packet to wake it up again.
+pub trait Monitor {
+    fn receive(&mut self, packet: Packet);
+    fn on_idle(&mut self) -> Option<Packet>;
+}
+
+// Remembers only the last packet it was sent, and sends that to node 0
+// whenever the network goes idle.
+#[derive(Clone, Debug, Default)]
+pub struct Nat {
+    last: Option<Packet>,
+}
+
+impl Nat {
+    pub fn new() -> Nat {
+        Nat { last: None }
+    }
+
+    pub fn last_packet(&self) -> Option<Packet> {
+        self.last
+    }
+}
+
+impl Monitor for Nat {
+    fn receive(&mut self, packet: Packet) {
+        self.last = Some(packet);
+    }
+
+    fn on_idle(&mut self) -> Option<Packet> {
+        self.last.map(|packet| Packet {
+            source: packet.destination,
+            destination: 0,
+            x: packet.x,
+            y: packet.y,
+        })
+    }
+}
+
+struct Node {
+    machine: IntcodeMachine,
+    queue: VecDeque<(i64, i64)>,
+    partial: Vec<i64>,
+    halted: bool,
+}
+
+enum NodeStatus {
+    Waiting,
+    Busy,
+    Halted,
+}
+
+impl Node {
+    // Nodes are stepped rather than run, so breakpoints set on them are
+    // ignored.
+    fn run(&mut self, budget: usize) -> Result<NodeStatus, ExecutionError> {
+        for _ in 0..budget {
+            let step = self.machine.step()?;
+            self.partial.extend(step.output);
+            match step.status {
+                StepStatus::Running => {}
+                StepStatus::BlockedOnInput => return Ok(NodeStatus::Waiting),
+                StepStatus::Halted => return Ok(NodeStatus::Halted),
+            }
+        }
+        Ok(NodeStatus::Busy)
+    }
+}
+
+pub struct Network {
+    nodes: Vec<Node>,
+    monitor: Option<(i64, Box<dyn Monitor>)>,
+    step_budget: usize,
+    idle_rounds: usize,
+    quiet_rounds: usize,
+    round: usize,
+    log: Vec<LogEntry>,
+}
+
+impl Network {
+    // Boots `size` copies of `program`, each given its own address as its
+    // first input.
+    pub fn new(program: &[i64], size: usize) -> Network {
+        let nodes = (0..size)
+            .map(|address| Node {
+                machine: IntcodeMachine::with_seed(program.to_vec(), address as i64),
+                queue: VecDeque::new(),
+                partial: Vec::new(),
+                halted: false,
+            })
+            .collect();
+
+        Network {
+            nodes,
+            monitor: None,
+            step_budget: DEFAULT_STEP_BUDGET,
+            idle_rounds: DEFAULT_IDLE_ROUNDS,
+            quiet_rounds: 0,
+            round: 0,
+            log: Vec::new(),
+        }
+    }
+
+    pub fn set_monitor<M>(&mut self, address: i64, monitor: M)
+    where
+        M: Monitor + 'static,
+    {
+        self.monitor = Some((address, Box::new(monitor)));
+    }
+
+    pub fn set_idle_rounds(&mut self, rounds: usize) {
+        self.idle_rounds = rounds.max(1);
+    }
+
+    pub fn set_step_budget(&mut self, budget: usize) {
+        self.step_budget = budget.max(1);
+    }
+
+    pub fn size(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn node(&self, address: usize) -> &IntcodeMachine {
+        &self.nodes[address].machine
+    }
+
+    pub fn node_mut(&mut self, address: usize) -> &mut IntcodeMachine {
+        &mut self.nodes[address].machine
+    }
+
+    pub fn rounds(&self) -> usize {
+        self.round
+    }
+
+    pub fn log(&self) -> &[LogEntry] {
+        &self.log
+    }
+
+    // Queues a packet for a node as if another node had sent it.
+    pub fn send(&mut self, packet: Packet) -> Result<(), NetworkError> {
+        self.deliver(packet)
+    }
+
+    fn deliver(&mut self, packet: Packet) -> Result<(), NetworkError> {
+        if let Some((address, monitor)) = self.monitor.as_mut() {
+            if *address == packet.destination {
+                monitor.receive(packet);
+                return Ok(());
+            }
+        }
+
+        if packet.destination < 0 || packet.destination as usize >= self.nodes.len() {
+            return Err(NetworkError::UnknownDestination(packet));
+        }
+        self.nodes[packet.destination as usize]
+            .queue
+            .push_back((packet.x, packet.y));
+        Ok(())
+    }
+
+    fn record(&mut self, event: NetworkEvent, events: &mut Vec<NetworkEvent>) {
+        self.log.push(LogEntry {
+            round: self.round,
+            event,
+        });
+        events.push(event);
+    }
+
+    // Gives every node one turn, in address order. A node is given all of
+    // the packets waiting for it, or -1 if there are none and it has no
+    // input left, and then runs until it wants more input or has used up
+    // its step budget. Packets sent during the round are only received in
+    // the next one. A node which fails doesn't stop the others from taking
+    // their turns.
+    pub fn round(&mut self) -> Result<Vec<NetworkEvent>, NetworkError> {
+        if self.nodes.iter().all(|node| node.halted) {
+            return Err(NetworkError::AllHalted);
+        }
+
+        self.round += 1;
+        let mut events = Vec::new();
+        let mut sent = Vec::new();
+        let mut halts = Vec::new();
+        let mut failures = Vec::new();
+        let mut busy = false;
+
+        for (address, node) in self.nodes.iter_mut().enumerate() {
+            if node.halted {
+                continue;
+            }
+
+            if node.queue.is_empty() && node.machine.pending_inputs().next().is_none() {
+                node.machine.add_input(-1);
+            }
+            for (x, y) in node.queue.drain(..) {
+                node.machine.add_inputs(vec![x, y]);
+            }
+
+            let status = node.run(self.step_budget);
+            while node.partial.len() >= 3 {
+                let triple: Vec<i64> = node.partial.drain(..3).collect();
+                sent.push(Packet {
+                    source: address as i64,
+                    destination: triple[0],
+                    x: triple[1],
+                    y: triple[2],
+                });
+            }
+
+            match status {
+                Ok(NodeStatus::Waiting) => {}
+                Ok(NodeStatus::Busy) => busy = true,
+                Ok(NodeStatus::Halted) => {
+                    node.halted = true;
+                    halts.push(address as i64);
+                }
+                Err(error) => failures.push((address, error)),
+            }
+        }
+
+        // Everything sent during a round which fails is still delivered and
+        // logged, so a caller can deal with the error and carry on.
+        let mut failure = None;
+        if !failures.is_empty() {
+            failure = Some(NetworkError::Programs(failures));
+        }
+        for packet in sent.iter() {
+            self.record(NetworkEvent::Sent(*packet), &mut events);
+            if let Err(e) = self.deliver(*packet) {
+                failure.get_or_insert(e);
+            }
+        }
+        for address in halts {
+            self.record(NetworkEvent::Halted(address), &mut events);
+        }
+        if let Some(failure) = failure {
+            return Err(failure);
+        }
+
+        let queues_empty = self.nodes.iter().all(|node| node.queue.is_empty());
+        if sent.is_empty() && queues_empty && !busy {
+            self.quiet_rounds += 1;
+        } else {
+            self.quiet_rounds = 0;
+        }
+
+        if self.quiet_rounds >= self.idle_rounds {
+            self.quiet_rounds = 0;
+            self.record(NetworkEvent::Idle, &mut events);
+
+            let wake_up = match self.monitor.as_mut() {
+                Some((_, monitor)) => monitor.on_idle(),
+                None => None,
+            };
+            match wake_up {
+                Some(packet) => {
+                    self.record(NetworkEvent::Injected(packet), &mut events);
+                    self.deliver(packet)?;
+                }
+                None => return Err(NetworkError::Stalled),
+            }
+        }
+
+        Ok(events)
+    }
+
+    // Runs rounds until `stop` accepts an event, and returns that event. The
+    // rest of the round is still run.
+    pub fn run_until<F>(&mut self, mut stop: F) -> Result<NetworkEvent, NetworkError>
+    where
+        F: FnMut(&NetworkEvent) -> bool,
+    {
+        loop {
+            if let Some(event) = self.round()?.into_iter().find(|event| stop(event)) {
+                return Ok(event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    // Three nodes pass a packet around, each adding one to x, and the last
+    // sends it on to the NAT. Node 0 starts things off.
+    fn relay() -> Vec<i64> {
+        assemble(
+            "
+                    IN [addr]
+                    ADD [addr], 1, [next]
+                    EQ [next], 3, [wrap]
+                    JF [wrap], check
+                    ADD 255, 0, [next]
+            check:  JT [addr], loop
+                    OUT 1
+                    OUT 0
+                    OUT 100
+            loop:   IN [x]
+                    EQ [x], -1, [empty]
+                    JT [empty], loop
+                    IN [y]
+                    ADD [x], 1, [x]
+                    OUT [next]
+                    OUT [x]
+                    OUT [y]
+                    JT 1, loop
+            addr:   DATA 0
+            next:   DATA 0
+            wrap:   DATA 0
+            empty:  DATA 0
+            x:      DATA 0
+            y:      DATA 0
+            ",
+        )
+        .unwrap()
+    }
+
+    fn packet(source: i64, destination: i64, x: i64, y: i64) -> Packet {
+        Packet {
+            source,
+            destination,
+            x,
+            y,
+        }
+    }
+
+    #[test]
+    fn packets_travel_between_nodes() {
+        let mut network = Network::new(&relay(), 3);
+        network.set_monitor(NAT_ADDRESS, Nat::new());
+
+        let event = network
+            .run_until(|event| match event {
+                NetworkEvent::Sent(packet) => packet.destination == NAT_ADDRESS,
+                _ => false,
+            })
+            .unwrap();
+
+        assert_eq!(event, NetworkEvent::Sent(packet(2, 255, 2, 100)));
+        assert_eq!(network.rounds(), 3);
+        assert_eq!(
+            network.log(),
+            &[
+                LogEntry {
+                    round: 1,
+                    event: NetworkEvent::Sent(packet(0, 1, 0, 100))
+                },
+                LogEntry {
+                    round: 2,
+                    event: NetworkEvent::Sent(packet(1, 2, 1, 100))
+                },
+                LogEntry {
+                    round: 3,
+                    event: NetworkEvent::Sent(packet(2, 255, 2, 100))
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn nat_wakes_an_idle_network() {
+        let mut network = Network::new(&relay(), 3);
+        network.set_monitor(NAT_ADDRESS, Nat::new());
+
+        let mut injected = Vec::new();
+        network
+            .run_until(|event| match event {
+                NetworkEvent::Injected(packet) => {
+                    injected.push(*packet);
+                    injected.len() == 2
+                }
+                _ => false,
+            })
+            .unwrap();
+
+        assert_eq!(
+            injected,
+            vec![packet(255, 0, 2, 100), packet(255, 0, 5, 100)]
+        );
+        let idle_rounds: Vec<usize> = network
+            .log()
+            .iter()
+            .filter(|entry| entry.event == NetworkEvent::Idle)
+            .map(|entry| entry.round)
+            .collect();
+        assert_eq!(idle_rounds, vec![5, 10]);
+    }
+
+    #[test]
+    fn idle_network_without_monitor_stalls() {
+        // Waits for packets forever.
+        let mut network = Network::new(&[3, 100, 1105, 1, 0], 2);
+        assert_eq!(network.run_until(|_| false), Err(NetworkError::Stalled));
+        assert_eq!(network.rounds(), 2);
+    }
+
+    #[test]
+    fn packets_need_a_destination() {
+        let mut network = Network::new(&relay(), 3);
+        assert_eq!(
+            network.run_until(|_| false),
+            Err(NetworkError::UnknownDestination(packet(2, 255, 2, 100)))
+        );
+        assert_eq!(
+            network.send(packet(0, -1, 0, 0)),
+            Err(NetworkError::UnknownDestination(packet(0, -1, 0, 0)))
+        );
+    }
+
+    #[test]
+    fn failures_keep_the_rest_of_the_round() {
+        // Node 0 sends to a missing node and then to node 1, which crashes.
+        let program = assemble(
+            "
+                    IN [addr]
+                    JT [addr], crash
+                    OUT 7
+                    OUT 0
+                    OUT 0
+                    OUT 1
+                    OUT 5
+                    OUT 6
+            loop:   IN [addr]
+                    JT 1, loop
+            crash:  DATA 0
+            addr:   DATA 0
+            ",
+        )
+        .unwrap();
+
+        let mut network = Network::new(&program, 2);
+        match network.round() {
+            Err(NetworkError::Programs(failures)) => {
+                assert_eq!(failures.len(), 1);
+                assert_eq!(failures[0].0, 1);
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+        assert_eq!(network.nodes[1].queue, vec![(5, 6)]);
+        assert_eq!(network.log().len(), 2);
+
+        let mut network = Network::new(&program, 1);
+        assert_eq!(
+            network.round(),
+            Err(NetworkError::UnknownDestination(packet(0, 7, 0, 0)))
+        );
+        assert_eq!(
+            network.log()[1].event,
+            NetworkEvent::Sent(packet(0, 1, 5, 6))
+        );
+    }
+
+    #[test]
+    fn every_node_takes_its_turn() {
+        // Node 0 crashes; node 1 sends itself a packet, then crashes too.
+        let program = assemble(
+            "
+                    IN [addr]
+                    JF [addr], crash
+                    OUT 1
+                    OUT 2
+                    OUT 3
+            crash:  DATA 0
+            addr:   DATA 0
+            ",
+        )
+        .unwrap();
+
+        let mut network = Network::new(&program, 2);
+        match network.round() {
+            Err(NetworkError::Programs(failures)) => {
+                let nodes: Vec<usize> = failures.iter().map(|&(node, _)| node).collect();
+                assert_eq!(nodes, vec![0, 1]);
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+        assert_eq!(
+            network.log()[0].event,
+            NetworkEvent::Sent(packet(1, 1, 2, 3))
+        );
+    }
+
+    #[test]
+    fn busy_nodes_use_up_their_budget() {
+        // Node 0 loops without ever asking for input.
+        let program = assemble(
+            "
+                    IN [addr]
+                    JF [addr], spin
+            wait:   IN [addr]
+                    JT 1, wait
+            spin:   JT 1, spin
+            addr:   DATA 0
+            ",
+        )
+        .unwrap();
+
+        let mut network = Network::new(&program, 2);
+        network.set_step_budget(100);
+        for _ in 0..5 {
+            assert_eq!(network.round(), Ok(vec![]));
+        }
+        assert_eq!(network.node(0).instruction_count(), 5 * 100);
+    }
+
+    #[test]
+    fn halted_nodes_are_skipped() {
+        // Sends its address to node 0, then halts.
+        let program = vec![3, 9, 104, 0, 4, 9, 104, 8, 99, 0];
+        let mut network = Network::new(&program, 2);
+        assert_eq!(
+            network.round(),
+            Ok(vec![
+                NetworkEvent::Sent(packet(0, 0, 0, 8)),
+                NetworkEvent::Sent(packet(1, 0, 1, 8)),
+                NetworkEvent::Halted(0),
+                NetworkEvent::Halted(1),
+            ])
+        );
+        assert_eq!(network.round(), Err(NetworkError::AllHalted));
+    }
+}
@@ -0,0 +1,253 @@
+use crate::breakpoints::Breakpoint;
+use crate::errors::ExecutionError;
+use crate::IntcodeMachine;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum OutputEvent<T> {
+    Output(T),
+    NeedsInput,
+    Halted,
+    Breakpoint(Breakpoint),
+}
+
+// Yields the machine's outputs one at a time, running it only as far as the
+// next output. `NeedsInput` is yielded whenever the program asks for input
+// that has not been queued; add some with `add_input` before asking for
+// the next item, or the iterator ends there. It also ends when the program
+// halts, or after yielding an error.
+pub struct Outputs<'a> {
+    machine: &'a mut IntcodeMachine,
+    waiting: bool,
+    finished: bool,
+}
+
+impl<'a> Outputs<'a> {
+    pub fn new(machine: &'a mut IntcodeMachine) -> Outputs<'a> {
+        Outputs {
+            machine,
+            waiting: false,
+            finished: false,
+        }
+    }
+
+    pub fn add_input(&mut self, input: i64) {
+        self.machine.add_input(input);
+    }
+
+    pub fn machine(&mut self) -> &mut IntcodeMachine {
+        self.machine
+    }
+}
+
+impl<'a> Iterator for Outputs<'a> {
+    type Item = Result<OutputEvent<i64>, ExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        if self.waiting && self.machine.pending_inputs().next().is_none() {
+            self.finished = true;
+            return None;
+        }
+
+        let event = self.machine.run_until_output();
+        self.waiting = event == Ok(OutputEvent::NeedsInput);
+        match event {
+            Ok(OutputEvent::Halted) => {
+                self.finished = true;
+                None
+            }
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+            event => Some(event),
+        }
+    }
+}
+
+// As `Outputs`, but yields fixed-size groups such as the `(x, y, tile)`
+// triples of an arcade game.
+pub struct OutputGroups<'a> {
+    machine: &'a mut IntcodeMachine,
+    size: usize,
+    waiting: bool,
+    finished: bool,
+}
+
+impl<'a> OutputGroups<'a> {
+    pub fn new(machine: &'a mut IntcodeMachine, size: usize) -> OutputGroups<'a> {
+        assert!(size > 0, "Output groups cannot be empty");
+        OutputGroups {
+            machine,
+            size,
+            waiting: false,
+            finished: false,
+        }
+    }
+
+    pub fn add_input(&mut self, input: i64) {
+        self.machine.add_input(input);
+    }
+
+    pub fn machine(&mut self) -> &mut IntcodeMachine {
+        self.machine
+    }
+}
+
+impl<'a> Iterator for OutputGroups<'a> {
+    type Item = Result<OutputEvent<Vec<i64>>, ExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        if self.waiting && self.machine.pending_inputs().next().is_none() {
+            self.finished = true;
+            return None;
+        }
+
+        let event = self.machine.run_until_outputs(self.size);
+        self.waiting = event == Ok(OutputEvent::NeedsInput);
+        match event {
+            Ok(OutputEvent::Halted) => {
+                self.finished = true;
+                None
+            }
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+            event => Some(event),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ProgramError;
+    use crate::ProgramState;
+
+    #[test]
+    fn run_until_output_pauses_between_values() {
+        let mut machine = IntcodeMachine::new(vec![104, 1, 104, 2, 99]);
+        assert_eq!(machine.run_until_output(), Ok(OutputEvent::Output(1)));
+        assert_eq!(machine.instruction_pointer(), 2);
+        assert_eq!(machine.run_until_output(), Ok(OutputEvent::Output(2)));
+        assert_eq!(machine.run_until_output(), Ok(OutputEvent::Halted));
+        assert_eq!(machine.run_until_output(), Ok(OutputEvent::Halted));
+    }
+
+    #[test]
+    fn needing_input_is_reported() {
+        let mut machine = IntcodeMachine::new(vec![3, 5, 4, 5, 99, 0]);
+        assert_eq!(machine.run_until_output(), Ok(OutputEvent::NeedsInput));
+        machine.add_input(12);
+        assert_eq!(machine.run_until_output(), Ok(OutputEvent::Output(12)));
+    }
+
+    #[test]
+    fn groups_survive_a_pause_for_input() {
+        // OUT 1, IN [x], OUT [x], OUT 3, HLT
+        let program = vec![104, 1, 3, 11, 4, 11, 104, 3, 99, 0, 0, 0];
+        let mut machine = IntcodeMachine::new(program);
+        assert_eq!(machine.run_until_outputs(2), Ok(OutputEvent::NeedsInput));
+        machine.add_input(2);
+        assert_eq!(
+            machine.run_until_outputs(2),
+            Ok(OutputEvent::Output(vec![1, 2]))
+        );
+        assert_eq!(
+            machine.run_until_outputs(2),
+            Ok(OutputEvent::Output(vec![3]))
+        );
+        assert_eq!(machine.run_until_outputs(2), Ok(OutputEvent::Halted));
+    }
+
+    #[test]
+    fn buffered_outputs_are_returned_one_at_a_time() {
+        // OUT 1, OUT 2, IN [x], OUT [x], HLT
+        let mut machine = IntcodeMachine::new(vec![104, 1, 104, 2, 3, 11, 4, 11, 99, 0, 0, 0]);
+        assert_eq!(machine.run_until_outputs(3), Ok(OutputEvent::NeedsInput));
+        assert_eq!(machine.run_until_output(), Ok(OutputEvent::Output(1)));
+        assert_eq!(machine.run_until_output(), Ok(OutputEvent::Output(2)));
+        assert_eq!(machine.run_until_output(), Ok(OutputEvent::NeedsInput));
+        machine.add_input(3);
+        assert_eq!(machine.run_until_output(), Ok(OutputEvent::Output(3)));
+    }
+
+    #[test]
+    #[should_panic]
+    fn empty_groups_are_rejected() {
+        IntcodeMachine::new(vec![99]).output_groups(0);
+    }
+
+    #[test]
+    fn output_iterator_yields_lazily() {
+        let mut machine = IntcodeMachine::new(vec![104, 1, 104, 2, 104, 3, 99]);
+        let first: Vec<_> = machine.outputs().take(2).collect();
+        assert_eq!(
+            first,
+            vec![Ok(OutputEvent::Output(1)), Ok(OutputEvent::Output(2))]
+        );
+        assert_eq!(machine.run(), Ok(ProgramState::Completed(vec![3])));
+    }
+
+    #[test]
+    fn groups_iterator_with_input() {
+        // Reads a value and outputs it with its double, until given 0.
+        let program = vec![
+            3, 20, 1006, 20, 19, 4, 20, 1002, 20, 2, 21, 4, 21, 1105, 1, 0, 0, 0, 0, 99, 0, 0,
+        ];
+        let mut machine = IntcodeMachine::new(program);
+        let mut groups = machine.output_groups(2);
+        let mut seen = Vec::new();
+        let mut inputs = vec![3, 5, 0].into_iter();
+
+        while let Some(event) = groups.next() {
+            match event.unwrap() {
+                OutputEvent::Output(pair) => seen.push(pair),
+                OutputEvent::NeedsInput => groups.add_input(inputs.next().unwrap()),
+                other => panic!("Unexpected {:?}", other),
+            }
+        }
+
+        assert_eq!(seen, vec![vec![3, 6], vec![5, 10]]);
+    }
+
+    #[test]
+    fn iterators_end_when_input_runs_out() {
+        // OUT 1, IN [x], OUT [x], HLT
+        let program = vec![104, 1, 3, 9, 4, 9, 99, 0, 0, 0];
+        let mut machine = IntcodeMachine::new(program.clone());
+        let events: Vec<_> = machine.outputs().collect();
+        assert_eq!(
+            events,
+            vec![Ok(OutputEvent::Output(1)), Ok(OutputEvent::NeedsInput)]
+        );
+
+        let mut machine = IntcodeMachine::new(program);
+        let groups: Vec<_> = machine.output_groups(2).collect();
+        assert_eq!(groups, vec![Ok(OutputEvent::NeedsInput)]);
+    }
+
+    #[test]
+    fn iterator_stops_after_an_error() {
+        let mut machine = IntcodeMachine::new(vec![104, 1, 0]);
+        let events: Vec<_> = machine
+            .outputs()
+            .map(|event| event.map_err(|e| e.kind))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                Ok(OutputEvent::Output(1)),
+                Err(ProgramError::UnknownOpcode(0))
+            ]
+        );
+    }
+}
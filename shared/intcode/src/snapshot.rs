@@ -0,0 +1,240 @@
+use crate::errors::SnapshotError;
+use crate::ProgramStore;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+// File layout, all integers little-endian:
+//
+//   magic "ICSN", version (u32)
+//   instruction pointer (u64), relative base (i64)
+//   instruction count (u64, from version 2)
+//   input count (u64), inputs (i64 each)
+//   cell count (u64), cells (u64 address, i64 value each)
+const MAGIC: &[u8; 4] = b"ICSN";
+const VERSION: u32 = 2;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Snapshot {
+    pub memory: ProgramStore,
+    pub instruction_pointer: usize,
+    pub relative_base: i64,
+    pub instruction_count: u64,
+    pub input_queue: VecDeque<i64>,
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64, SnapshotError> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_i64<R: Read>(reader: &mut R) -> Result<i64, SnapshotError> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(i64::from_le_bytes(bytes))
+}
+
+impl Snapshot {
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<(), SnapshotError> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(self.instruction_pointer as u64).to_le_bytes())?;
+        writer.write_all(&self.relative_base.to_le_bytes())?;
+        writer.write_all(&self.instruction_count.to_le_bytes())?;
+
+        writer.write_all(&(self.input_queue.len() as u64).to_le_bytes())?;
+        for input in &self.input_queue {
+            writer.write_all(&input.to_le_bytes())?;
+        }
+
+        // Cells holding zero are kept too, so that limits on the number of
+        // cells see the same memory after a restore.
+        let cells: Vec<(usize, i64)> = self.memory.cells().collect();
+        writer.write_all(&(cells.len() as u64).to_le_bytes())?;
+        for (address, value) in cells {
+            writer.write_all(&(address as u64).to_le_bytes())?;
+            writer.write_all(&value.to_le_bytes())?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Snapshot, SnapshotError> {
+        let mut magic = [0u8; 4];
+        reader
+            .read_exact(&mut magic)
+            .map_err(|_| SnapshotError::NotASnapshot)?;
+        if &magic != MAGIC {
+            return Err(SnapshotError::NotASnapshot);
+        }
+
+        let mut version = [0u8; 4];
+        reader.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        if version != 1 && version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let instruction_pointer = read_u64(&mut reader)? as usize;
+        let relative_base = read_i64(&mut reader)?;
+        let instruction_count = if version == 1 {
+            0
+        } else {
+            read_u64(&mut reader)?
+        };
+
+        let input_count = read_u64(&mut reader)?;
+        let input_queue = (0..input_count)
+            .map(|_| read_i64(&mut reader))
+            .collect::<Result<VecDeque<i64>, SnapshotError>>()?;
+
+        let cell_count = read_u64(&mut reader)?;
+        let mut memory = ProgramStore::new();
+        for _ in 0..cell_count {
+            let address = read_u64(&mut reader)? as usize;
+            memory[address] = read_i64(&mut reader)?;
+        }
+
+        Ok(Snapshot {
+            memory,
+            instruction_pointer,
+            relative_base,
+            instruction_count,
+            input_queue,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SnapshotError> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Snapshot, SnapshotError> {
+        Snapshot::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IntcodeMachine, ProgramState};
+
+    // Echoes each input doubled, forever.
+    fn doubler() -> Vec<i64> {
+        vec![3, 11, 1002, 11, 2, 11, 4, 11, 1105, 1, 0, 0]
+    }
+
+    #[test]
+    fn restore_rewinds_the_machine() {
+        let mut machine = IntcodeMachine::new(doubler());
+        machine.add_input(4);
+        assert_eq!(machine.run(), Ok(ProgramState::PendingInput(vec![8])));
+
+        let snapshot = machine.snapshot();
+        machine.add_inputs(vec![5, 6]);
+        assert_eq!(machine.run(), Ok(ProgramState::PendingInput(vec![10, 12])));
+
+        machine.restore(&snapshot);
+        machine.add_input(7);
+        assert_eq!(machine.run(), Ok(ProgramState::PendingInput(vec![14])));
+    }
+
+    #[test]
+    fn snapshots_include_pending_input() {
+        let mut machine = IntcodeMachine::new(doubler());
+        machine.add_inputs(vec![1, 2]);
+        let snapshot = machine.snapshot();
+        assert_eq!(snapshot.input_queue, vec![1, 2]);
+
+        let mut branch = IntcodeMachine::from(snapshot);
+        assert_eq!(branch.run(), Ok(ProgramState::PendingInput(vec![2, 4])));
+    }
+
+    #[test]
+    fn cloned_machines_run_independently() {
+        let mut machine = IntcodeMachine::new(doubler());
+        machine.add_input(1);
+        let mut branch = machine.clone();
+        branch.add_input(2);
+
+        assert_eq!(machine.run(), Ok(ProgramState::PendingInput(vec![2])));
+        assert_eq!(branch.run(), Ok(ProgramState::PendingInput(vec![2, 4])));
+    }
+
+    #[test]
+    fn file_format_round_trips() {
+        let mut machine = IntcodeMachine::new(vec![109, -3, 3, 1000, 99]);
+        machine.add_inputs(vec![-1, 1_125_899_906_842_624]);
+        machine.step().unwrap();
+        machine.step().unwrap();
+
+        let snapshot = machine.snapshot();
+        let mut bytes = Vec::new();
+        snapshot.write_to(&mut bytes).unwrap();
+
+        let loaded = Snapshot::read_from(&bytes[..]).unwrap();
+        assert_eq!(loaded, snapshot);
+        assert_eq!(loaded.relative_base, -3);
+        assert_eq!(loaded.memory[1000], -1);
+    }
+
+    #[test]
+    fn round_trip_keeps_zero_cells_and_instruction_count() {
+        let mut machine = IntcodeMachine::new(vec![1101, 0, 0, 20, 99]);
+        assert_eq!(machine.run(), Ok(ProgramState::Completed(vec![])));
+
+        let snapshot = machine.snapshot();
+        let mut bytes = Vec::new();
+        snapshot.write_to(&mut bytes).unwrap();
+        let loaded = Snapshot::read_from(&bytes[..]).unwrap();
+        assert_eq!(loaded.memory.cell_count(), snapshot.memory.cell_count());
+        assert_eq!(loaded.instruction_count, 1);
+
+        let mut restored = IntcodeMachine::new(doubler());
+        restored.restore(&loaded);
+        assert_eq!(restored.instruction_count(), 1);
+        assert_eq!(restored.memory().cell_count(), 6);
+    }
+
+    #[test]
+    fn save_and_load_file() {
+        let path = std::env::temp_dir().join(format!(
+            "intcode_save_and_load_file_{}.icsn",
+            std::process::id()
+        ));
+        let snapshot = IntcodeMachine::new(doubler()).snapshot();
+        snapshot.save(&path).unwrap();
+        let loaded = Snapshot::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, snapshot);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        assert!(matches!(
+            Snapshot::read_from(&b"nope"[..]),
+            Err(SnapshotError::NotASnapshot)
+        ));
+
+        let mut bytes = Vec::new();
+        IntcodeMachine::new(doubler())
+            .snapshot()
+            .write_to(&mut bytes)
+            .unwrap();
+
+        let mut future = bytes.clone();
+        future[4] = 3;
+        assert!(matches!(
+            Snapshot::read_from(&future[..]),
+            Err(SnapshotError::UnsupportedVersion(3))
+        ));
+
+        bytes.truncate(bytes.len() - 3);
+        assert!(matches!(
+            Snapshot::read_from(&bytes[..]),
+            Err(SnapshotError::Truncated)
+        ));
+    }
+}
@@ -0,0 +1,243 @@
+use core::ops::{Index, IndexMut};
+use std::collections::BTreeMap;
+use std::iter::FromIterator;
+
+// Writes this close to the end of the dense region grow it rather than
+// going into the sparse map.
+const DENSE_SLACK: usize = 1024;
+
+// Memory for an Intcode program. The loaded image, and any addresses just
+// beyond it, live in a contiguous vector; writes to distant addresses go
+// into a sparse map. Every address reads as 0 until it is written.
+#[derive(Clone, Debug, Default)]
+pub struct ProgramStore {
+    dense: Vec<i64>,
+    // Which dense cells have been loaded or written, as opposed to padding
+    // added when the dense region grew.
+    used: Vec<bool>,
+    used_count: usize,
+    sparse: BTreeMap<usize, i64>,
+}
+
+impl ProgramStore {
+    pub fn new() -> ProgramStore {
+        ProgramStore {
+            dense: Vec::new(),
+            used: Vec::new(),
+            used_count: 0,
+            sparse: BTreeMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        // A write through a negative address lands near usize::MAX.
+        let sparse_len = self
+            .sparse
+            .keys()
+            .next_back()
+            .map_or(0, |&last| last.saturating_add(1));
+        self.dense.len().max(sparse_len)
+    }
+
+    // The length of the contiguous region holding the loaded image.
+    pub fn dense_len(&self) -> usize {
+        self.dense.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dense.is_empty() && self.sparse.is_empty()
+    }
+
+    // Every cell which has been loaded or written, in address order.
+    pub fn cells(&self) -> impl Iterator<Item = (usize, i64)> + '_ {
+        self.dense
+            .iter()
+            .cloned()
+            .enumerate()
+            .filter(move |&(address, _)| self.used[address])
+            .chain(self.sparse.iter().map(|(&address, &value)| (address, value)))
+    }
+
+    // How many distinct cells have been loaded or written.
+    pub fn cell_count(&self) -> usize {
+        self.used_count + self.sparse.len()
+    }
+
+    // What `cell_count` would be after writing to `index`.
+    pub fn cell_count_after_write(&self, index: usize) -> usize {
+        let used = match self.used.get(index) {
+            Some(&used) => used,
+            None => self.sparse.contains_key(&index),
+        };
+        if used {
+            self.cell_count()
+        } else {
+            self.cell_count() + 1
+        }
+    }
+
+    fn grows_dense(&self, index: usize) -> bool {
+        let len = self.dense.len();
+        index >= len && index - len < DENSE_SLACK.max(len)
+    }
+
+    fn grow_dense(&mut self, index: usize) {
+        let start = self.dense.len();
+        self.dense.resize(index + 1, 0);
+        self.used.resize(index + 1, false);
+
+        let moved: Vec<usize> = self.sparse.range(start..=index).map(|(&k, _)| k).collect();
+        for address in moved {
+            if let Some(value) = self.sparse.remove(&address) {
+                self.dense[address] = value;
+                self.used[address] = true;
+                self.used_count += 1;
+            }
+        }
+    }
+}
+
+// Two stores are equal if every address reads the same, however the cells
+// happen to be laid out.
+impl PartialEq for ProgramStore {
+    fn eq(&self, other: &Self) -> bool {
+        let nonzero = |&(_, value): &(usize, i64)| value != 0;
+        self.cells().filter(nonzero).eq(other.cells().filter(nonzero))
+    }
+}
+
+impl Eq for ProgramStore {}
+
+impl Index<usize> for ProgramStore {
+    type Output = i64;
+    fn index(&self, index: usize) -> &Self::Output {
+        match self.dense.get(index) {
+            Some(value) => value,
+            None => self.sparse.get(&index).unwrap_or(&0),
+        }
+    }
+}
+
+impl IndexMut<usize> for ProgramStore {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        if self.grows_dense(index) {
+            self.grow_dense(index);
+        }
+
+        if index < self.dense.len() {
+            if !self.used[index] {
+                self.used[index] = true;
+                self.used_count += 1;
+            }
+            &mut self.dense[index]
+        } else {
+            self.sparse.entry(index).or_insert(0)
+        }
+    }
+}
+
+impl FromIterator<i64> for ProgramStore {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = i64>,
+    {
+        let dense: Vec<i64> = iter.into_iter().collect();
+        ProgramStore {
+            used: vec![true; dense.len()],
+            used_count: dense.len(),
+            dense,
+            sparse: BTreeMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_near_the_image_stay_dense() {
+        let mut program: ProgramStore = vec![1, 2, 3].into_iter().collect();
+        program[10] = 5;
+        assert_eq!(program.dense.len(), 11);
+        assert!(program.sparse.is_empty());
+        assert_eq!(program[10], 5);
+        assert_eq!(program[7], 0);
+        assert_eq!(program.len(), 11);
+    }
+
+    #[test]
+    fn distant_writes_are_sparse() {
+        let mut program: ProgramStore = vec![1, 2, 3].into_iter().collect();
+        program[1_000_000] = 9;
+        assert_eq!(program.dense.len(), 3);
+        assert_eq!(program[1_000_000], 9);
+        assert_eq!(program[999_999], 0);
+        assert_eq!(program.len(), 1_000_001);
+    }
+
+    #[test]
+    fn cell_count_after_write_matches_write() {
+        let mut program: ProgramStore = vec![1, 2, 3].into_iter().collect();
+        for &index in &[1, 10, 5000, 2000, 9, 1500, 1000, 1600, 2000] {
+            let expected = program.cell_count_after_write(index);
+            program[index] = 1;
+            assert_eq!(program.cell_count(), expected, "writing {}", index);
+        }
+        assert_eq!(program.cell_count(), 3 + 7);
+    }
+
+    #[test]
+    fn padding_is_not_counted() {
+        let mut program: ProgramStore = vec![1, 2, 3, 4, 5].into_iter().collect();
+        program[1000] = 0;
+        assert_eq!(program.dense.len(), 1001);
+        assert_eq!(program.cell_count(), 6);
+        assert_eq!(
+            program
+                .cells()
+                .map(|(address, _)| address)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 4, 1000]
+        );
+    }
+
+    #[test]
+    fn sparse_cells_move_into_dense_region_when_it_grows() {
+        let mut program = ProgramStore::new();
+        program[5000] = 42;
+        program[5001] = 43;
+        assert_eq!(program.sparse.len(), 2);
+
+        for index in (0..=5000).step_by(500) {
+            program[index] = 1;
+        }
+
+        assert!(program.sparse.contains_key(&5001));
+        assert!(!program.sparse.contains_key(&5000));
+        assert_eq!(program[5000], 1);
+        assert_eq!(program[5001], 43);
+    }
+
+    #[test]
+    fn equality_ignores_layout() {
+        let mut dense: ProgramStore = vec![1, 0, 0, 4].into_iter().collect();
+        let mut sparse = ProgramStore::new();
+        sparse[3] = 4;
+        sparse[0] = 1;
+        assert_eq!(dense, sparse);
+
+        dense[1_000_000] = 1;
+        assert_ne!(dense, sparse);
+        sparse[1_000_000] = 1;
+        assert_eq!(dense, sparse);
+    }
+
+    #[test]
+    fn empty_store() {
+        let program = ProgramStore::new();
+        assert!(program.is_empty());
+        assert_eq!(program.len(), 0);
+        assert_eq!(program[usize::MAX], 0);
+    }
+}
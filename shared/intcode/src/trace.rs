@@ -0,0 +1,402 @@
+use crate::instructions::Instruction;
+use crate::{MemoryWrite, ProgramStore};
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// One executed instruction. `operands` holds every argument resolved to the
+// value it read, or for a destination the address it wrote to.
+// `relative_base` is only set when the instruction changed it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TraceEvent {
+    pub address: usize,
+    pub instruction: Instruction,
+    pub operands: Vec<i64>,
+    pub write: Option<MemoryWrite>,
+    pub output: Option<i64>,
+    pub relative_base: Option<i64>,
+}
+
+pub trait TraceSink {
+    fn record(&mut self, event: &TraceEvent);
+}
+
+// Lets the caller keep a handle on a sink after giving it to a machine.
+impl<S: TraceSink> TraceSink for Arc<Mutex<S>> {
+    fn record(&mut self, event: &TraceEvent) {
+        if let Ok(mut sink) = self.lock() {
+            sink.record(event);
+        }
+    }
+}
+
+impl TraceSink for Vec<TraceEvent> {
+    fn record(&mut self, event: &TraceEvent) {
+        self.push(event.clone());
+    }
+}
+
+// Writes one line per instruction. Writing stops at the first I/O error,
+// which is kept so that it can be reported afterwards.
+pub struct TextTrace<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> TextTrace<W> {
+    pub fn new(writer: W) -> TextTrace<W> {
+        TextTrace {
+            writer,
+            error: None,
+        }
+    }
+
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    pub fn into_inner(mut self) -> io::Result<W> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => {
+                self.writer.flush()?;
+                Ok(self.writer)
+            }
+        }
+    }
+}
+
+pub fn format_event(event: &TraceEvent) -> String {
+    let operands: Vec<String> = event.operands.iter().map(|v| v.to_string()).collect();
+    let mut line = format!(
+        "{:>6}: {:<32} ({})",
+        event.address,
+        event.instruction.to_string(),
+        operands.join(", ")
+    );
+    if let Some(write) = event.write {
+        line.push_str(&format!(" [{}] <- {}", write.address, write.value));
+    }
+    if let Some(output) = event.output {
+        line.push_str(&format!(" out {}", output));
+    }
+    if let Some(base) = event.relative_base {
+        line.push_str(&format!(" rb <- {}", base));
+    }
+    line
+}
+
+impl<W: Write> TraceSink for TextTrace<W> {
+    fn record(&mut self, event: &TraceEvent) {
+        if self.error.is_none() {
+            if let Err(e) = writeln!(self.writer, "{}", format_event(event)) {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+const MAGIC: &[u8; 4] = b"ICTR";
+const VERSION: u8 = 1;
+
+const HAS_WRITE: u8 = 1;
+const HAS_OUTPUT: u8 = 2;
+const HAS_RELATIVE_BASE: u8 = 4;
+
+// Zigzag-encoded LEB128, so that small negative numbers stay small.
+fn write_varint<W: Write>(writer: &mut W, value: i64) -> io::Result<()> {
+    let mut n = ((value << 1) ^ (value >> 63)) as u64;
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_byte<R: Read>(reader: &mut R) -> io::Result<Option<u8>> {
+    let mut byte = [0u8];
+    match reader.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> io::Result<i64> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = read_byte(reader)?.ok_or_else(|| invalid_data("Truncated trace record"))?;
+        n |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(((n >> 1) as i64) ^ -((n & 1) as i64));
+        }
+    }
+    Err(invalid_data("Varint is too long"))
+}
+
+// A compact binary trace: a header followed by one variable-length record
+// per instruction. Use `read_binary_trace` to load it back.
+pub struct BinaryTrace<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl BinaryTrace<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<BinaryTrace<BufWriter<File>>> {
+        BinaryTrace::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> BinaryTrace<W> {
+    pub fn new(mut writer: W) -> io::Result<BinaryTrace<W>> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        Ok(BinaryTrace {
+            writer,
+            error: None,
+        })
+    }
+
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    pub fn into_inner(mut self) -> io::Result<W> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => {
+                self.writer.flush()?;
+                Ok(self.writer)
+            }
+        }
+    }
+
+    fn write_event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        let writer = &mut self.writer;
+        write_varint(writer, event.address as i64)?;
+        for word in event.instruction.encode() {
+            write_varint(writer, word)?;
+        }
+        for &operand in &event.operands {
+            write_varint(writer, operand)?;
+        }
+
+        let mut flags = 0;
+        if event.write.is_some() {
+            flags |= HAS_WRITE;
+        }
+        if event.output.is_some() {
+            flags |= HAS_OUTPUT;
+        }
+        if event.relative_base.is_some() {
+            flags |= HAS_RELATIVE_BASE;
+        }
+        writer.write_all(&[flags])?;
+
+        if let Some(write) = event.write {
+            write_varint(writer, write.address as i64)?;
+            write_varint(writer, write.value)?;
+        }
+        if let Some(output) = event.output {
+            write_varint(writer, output)?;
+        }
+        if let Some(base) = event.relative_base {
+            write_varint(writer, base)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> TraceSink for BinaryTrace<W> {
+    fn record(&mut self, event: &TraceEvent) {
+        if self.error.is_none() {
+            if let Err(e) = self.write_event(event) {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+fn read_event<R: Read>(reader: &mut R, first: u8) -> io::Result<TraceEvent> {
+    // The first byte of the record has already been consumed to detect the
+    // end of the file, so put it back in front of the reader.
+    let first = [first];
+    let mut reader = (&first[..]).chain(reader);
+
+    let address = read_varint(&mut reader)? as usize;
+    let opcode = read_varint(&mut reader)?;
+    let mut words: ProgramStore = ProgramStore::new();
+    words[0] = opcode;
+    let arity = Instruction::read(&words, 0)
+        .map_err(|_| invalid_data("Invalid instruction in trace"))?
+        .arity();
+    for index in 1..arity {
+        words[index] = read_varint(&mut reader)?;
+    }
+    let instruction =
+        Instruction::read(&words, 0).map_err(|_| invalid_data("Invalid instruction in trace"))?;
+
+    let operands = (1..arity)
+        .map(|_| read_varint(&mut reader))
+        .collect::<io::Result<Vec<i64>>>()?;
+
+    let flags = read_byte(&mut reader)?.ok_or_else(|| invalid_data("Truncated trace record"))?;
+    let write = if flags & HAS_WRITE != 0 {
+        let address = read_varint(&mut reader)? as usize;
+        let value = read_varint(&mut reader)?;
+        Some(MemoryWrite { address, value })
+    } else {
+        None
+    };
+    let output = if flags & HAS_OUTPUT != 0 {
+        Some(read_varint(&mut reader)?)
+    } else {
+        None
+    };
+    let relative_base = if flags & HAS_RELATIVE_BASE != 0 {
+        Some(read_varint(&mut reader)?)
+    } else {
+        None
+    };
+
+    Ok(TraceEvent {
+        address,
+        instruction,
+        operands,
+        write,
+        output,
+        relative_base,
+    })
+}
+
+pub fn read_binary_trace<R: Read>(mut reader: R) -> io::Result<Vec<TraceEvent>> {
+    let mut header = [0u8; 5];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != MAGIC {
+        return Err(invalid_data("Not an Intcode trace file"));
+    }
+    if header[4] != VERSION {
+        return Err(invalid_data("Unsupported trace file version"));
+    }
+
+    let mut events = Vec::new();
+    while let Some(first) = read_byte(&mut reader)? {
+        events.push(read_event(&mut reader, first)?);
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IntcodeMachine;
+
+    fn quine() -> Vec<i64> {
+        vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ]
+    }
+
+    #[test]
+    fn events_are_recorded_for_every_instruction() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut machine = IntcodeMachine::with_seed(vec![3, 9, 1001, 9, 5, 9, 4, 9, 99, 0], 37);
+        machine.set_trace_sink(events.clone());
+        machine.run().unwrap();
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0].operands, vec![9]);
+        assert_eq!(
+            events[0].write,
+            Some(MemoryWrite {
+                address: 9,
+                value: 37
+            })
+        );
+        assert_eq!(events[1].operands, vec![37, 5, 9]);
+        assert_eq!(events[2].output, Some(42));
+        assert_eq!(events[3].instruction, Instruction::Halt);
+    }
+
+    #[test]
+    fn relative_base_changes_are_recorded() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut machine = IntcodeMachine::new(quine());
+        machine.set_trace_sink(events.clone());
+        machine.run().unwrap();
+
+        let events = events.lock().unwrap();
+        let changes: Vec<i64> = events.iter().filter_map(|e| e.relative_base).collect();
+        assert_eq!(changes, (1..=16).collect::<Vec<i64>>());
+    }
+
+    #[test]
+    fn text_trace_format() {
+        let trace = Arc::new(Mutex::new(TextTrace::new(Vec::new())));
+        let mut machine = IntcodeMachine::new(vec![1101, 2, 3, 5, 99, 0]);
+        machine.set_trace_sink(trace.clone());
+        machine.run().unwrap();
+        drop(machine);
+
+        let trace = Arc::try_unwrap(trace).ok().unwrap().into_inner().unwrap();
+        let text = String::from_utf8(trace.into_inner().unwrap()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("     0: ADD 2, 3, [5]"));
+        assert!(lines[0].ends_with("(2, 3, 5) [5] <- 5"));
+        assert!(lines[1].starts_with("     4: HLT"));
+    }
+
+    #[test]
+    fn binary_trace_round_trips() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let binary = Arc::new(Mutex::new(BinaryTrace::new(Vec::new()).unwrap()));
+
+        let mut machine = IntcodeMachine::new(quine());
+        machine.set_trace_sink(events.clone());
+        machine.run().unwrap();
+
+        let mut machine = IntcodeMachine::new(quine());
+        machine.set_trace_sink(binary.clone());
+        machine.run().unwrap();
+        drop(machine);
+
+        let binary = Arc::try_unwrap(binary).ok().unwrap().into_inner().unwrap();
+        let bytes = binary.into_inner().unwrap();
+        let loaded = read_binary_trace(&bytes[..]).unwrap();
+
+        assert_eq!(loaded, *events.lock().unwrap());
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for &value in &[
+            0,
+            1,
+            -1,
+            63,
+            -64,
+            64,
+            1_125_899_906_842_624,
+            i64::MIN,
+            i64::MAX,
+        ] {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, value).unwrap();
+            assert_eq!(read_varint(&mut &bytes[..]).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(read_binary_trace(&b"HELLO"[..]).is_err());
+    }
+}
@@ -0,0 +1,243 @@
+use crate::errors::ProgramError;
+use crate::instructions::{Argument, Exit, Instruction};
+use crate::ProgramStore;
+use std::collections::BTreeSet;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Severity {
+    Note,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Note => write!(f, "note"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Problem {
+    UnknownOpcode(i64),
+    UnknownParameterMode(i64),
+    ImmediateDestination(i64),
+    // The instruction reaches past the end of the image, or the one after
+    // it would start there.
+    RunsOffEnd,
+    JumpOutOfRange(i64),
+    // The target comes from memory, so the jump can't be followed.
+    IndirectJump,
+    // Only writes through a position destination are caught. A relative
+    // destination is never flagged, even where the base is known.
+    WritesToCode(usize),
+}
+
+impl Problem {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Problem::UnknownOpcode(_)
+            | Problem::UnknownParameterMode(_)
+            | Problem::RunsOffEnd
+            | Problem::JumpOutOfRange(_) => Severity::Error,
+            Problem::ImmediateDestination(_) | Problem::WritesToCode(_) => Severity::Warning,
+            Problem::IndirectJump => Severity::Note,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::UnknownOpcode(opcode) => write!(f, "unknown opcode {}", opcode),
+            Problem::UnknownParameterMode(mode) => write!(f, "unknown parameter mode {}", mode),
+            Problem::ImmediateDestination(value) => {
+                write!(f, "writes to immediate-mode destination {}", value)
+            }
+            Problem::RunsOffEnd => write!(f, "runs off the end of the program"),
+            Problem::JumpOutOfRange(target) => write!(f, "jumps outside the program to {}", target),
+            Problem::IndirectJump => write!(f, "jump target is not known until run time"),
+            Problem::WritesToCode(address) => write!(f, "overwrites code at {}", address),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Diagnostic {
+    pub address: usize,
+    pub problem: Problem,
+}
+
+impl Diagnostic {
+    pub fn severity(&self) -> Severity {
+        self.problem.severity()
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.address, self.severity(), self.problem)
+    }
+}
+
+// Follows control flow from address 0, through every jump whose target is
+// given as an immediate value, and reports anything which looks wrong with
+// the instructions it reaches. Code only reached through indirect jumps is
+// not checked, and neither are writes into code through relative-mode
+// destinations.
+pub fn verify(program: &ProgramStore) -> Vec<Diagnostic> {
+    let end = program.len();
+    let mut diagnostics = BTreeSet::new();
+    let mut visited = BTreeSet::new();
+    let mut code = Vec::new();
+    let mut pending = vec![0];
+
+    while let Some(address) = pending.pop() {
+        if !visited.insert(address) {
+            continue;
+        }
+
+        let mut report = |problem| {
+            diagnostics.insert(Diagnostic { address, problem });
+        };
+
+        let instruction = match Instruction::read(program, address) {
+            Ok(instruction) => instruction,
+            Err(ProgramError::UnknownOpcode(opcode)) => {
+                report(Problem::UnknownOpcode(opcode));
+                continue;
+            }
+            Err(ProgramError::UnknownParameterMode(mode)) => {
+                report(Problem::UnknownParameterMode(mode));
+                continue;
+            }
+            Err(e) => unreachable!("Decoding can't fail with {:?}", e),
+        };
+        code.push((address, instruction));
+
+        if let Some(Argument::Immediate(value)) = instruction.destination() {
+            report(Problem::ImmediateDestination(value));
+        }
+
+        let mut targets = Vec::new();
+        for exit in instruction.exits(address) {
+            match exit {
+                Exit::FallThrough(next) => targets.push(next),
+                Exit::Jump(target) if target < 0 => report(Problem::JumpOutOfRange(target)),
+                Exit::Jump(target) => targets.push(target as usize),
+                Exit::Indirect => report(Problem::IndirectJump),
+            }
+        }
+        if address + instruction.arity() > end {
+            report(Problem::RunsOffEnd);
+            continue;
+        }
+        for target in targets {
+            if target < end {
+                pending.push(target);
+            } else if target == address + instruction.arity() {
+                report(Problem::RunsOffEnd);
+            } else {
+                report(Problem::JumpOutOfRange(target as i64));
+            }
+        }
+    }
+
+    let code_words: BTreeSet<usize> = code
+        .iter()
+        .flat_map(|(address, instruction)| *address..*address + instruction.arity())
+        .collect();
+    for (address, instruction) in code {
+        if let Some(Argument::Position(target)) = instruction.destination() {
+            if code_words.contains(&target) {
+                diagnostics.insert(Diagnostic {
+                    address,
+                    problem: Problem::WritesToCode(target),
+                });
+            }
+        }
+    }
+
+    diagnostics.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(program: Vec<i64>) -> Vec<(usize, Problem)> {
+        let program: ProgramStore = program.into_iter().collect();
+        verify(&program)
+            .into_iter()
+            .map(|diagnostic| (diagnostic.address, diagnostic.problem))
+            .collect()
+    }
+
+    #[test]
+    fn well_formed_programs_pass() {
+        assert_eq!(check(vec![3, 9, 1002, 9, 2, 9, 4, 9, 99, 0]), vec![]);
+    }
+
+    #[test]
+    fn only_reachable_code_is_checked() {
+        // The jump skips over an invalid opcode and a write to code, and the
+        // jump which is never taken doesn't count.
+        assert_eq!(
+            check(vec![1105, 1, 7, 77, 1101, 0, 0, 1106, 1, 1000, 99]),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn decoding_problems() {
+        assert_eq!(
+            check(vec![31101, 1, 1, 5, 99, 0]),
+            vec![(0, Problem::UnknownParameterMode(3))]
+        );
+        assert_eq!(
+            check(vec![1106, 0, 3, 77]),
+            vec![(3, Problem::UnknownOpcode(77))]
+        );
+        assert_eq!(
+            check(vec![11101, 1, 1, 5, 99, 0]),
+            vec![(0, Problem::ImmediateDestination(5))]
+        );
+    }
+
+    #[test]
+    fn control_flow_problems() {
+        assert_eq!(check(vec![104, 1]), vec![(0, Problem::RunsOffEnd)]);
+        assert_eq!(check(vec![99, 1, 1105, 1]), vec![]);
+        assert_eq!(check(vec![1105, 1, 3, 104]), vec![(3, Problem::RunsOffEnd)]);
+        assert_eq!(
+            check(vec![1005, 9, -1, 1105, 1, 500, 1006, 9, 10, 99]),
+            vec![
+                (0, Problem::JumpOutOfRange(-1)),
+                (3, Problem::JumpOutOfRange(500)),
+            ]
+        );
+        assert_eq!(
+            check(vec![5, 7, 8, 6, 7, 8, 99, 0, 0]),
+            vec![(0, Problem::IndirectJump), (3, Problem::IndirectJump)]
+        );
+    }
+
+    #[test]
+    fn writes_to_code_are_warned_about() {
+        let diagnostics = verify(&vec![1, 0, 0, 3, 1101, 1, 1, 0, 99].into_iter().collect());
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.to_string())
+                .collect::<Vec<String>>(),
+            vec![
+                "0: warning: overwrites code at 3",
+                "4: warning: overwrites code at 0"
+            ]
+        );
+    }
+}